@@ -0,0 +1,279 @@
+//! Derive macro for EJDB document models.
+//!
+//! This crate provides `#[derive(EjdbDocument)]` which implements `ejdb::EjdbDocument` trait
+//! for a structure, declaring its collection name, collection options and field indices.
+//! It is not supposed to be used directly; enable `derive` feature of `ejdb` crate instead.
+//!
+//! ```ignore
+//! #[derive(EjdbDocument)]
+//! #[ejdb(collection = "users", compressed, records = 1_000_000)]
+//! struct User {
+//!     #[ejdb(index = "string")]
+//!     name: String,
+//!     #[ejdb(index = "number")]
+//!     age: i32,
+//! }
+//! ```
+//!
+//! The following structure attributes are supported:
+//!
+//! * `collection = "name"` - the name of the collection; defaults to the structure name
+//!   in snake case;
+//! * `large`, `compressed` - boolean collection options, may also be written
+//!   as `large = true`;
+//! * `records = N`, `cached_records = N` - numeric collection options.
+//!
+//! The following field attributes are supported:
+//!
+//! * `index = "type"` - declares an index of the specified type on this field; possible
+//!   types are `string`, `istring` (case insensitive string), `number` and `array`. May be
+//!   repeated to declare several indices on one field;
+//! * `field = "name"` - the name of the document field; defaults to the `serde` name
+//!   of the field, if it is renamed, or to the field name itself.
+
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+extern crate syn;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use syn::spanned::Spanned;
+use syn::{Attribute, Data, DeriveInput, Error, Fields, Lit, Meta, NestedMeta};
+
+#[proc_macro_derive(EjdbDocument, attributes(ejdb))]
+pub fn derive_ejdb_document(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+#[derive(Default)]
+struct ContainerAttrs {
+    collection: Option<String>,
+    large: Option<bool>,
+    compressed: Option<bool>,
+    records: Option<i64>,
+    cached_records: Option<i32>,
+}
+
+enum IndexKind {
+    String,
+    CaseInsensitiveString,
+    Number,
+    Array,
+}
+
+struct FieldIndices {
+    field: String,
+    kinds: Vec<IndexKind>,
+}
+
+fn expand(input: &DeriveInput) -> Result<TokenStream2, Error> {
+    let attrs = parse_container_attrs(&input.attrs)?;
+
+    let fields = match input.data {
+        Data::Struct(ref data) => match data.fields {
+            Fields::Named(ref fields) => fields.named.iter().collect(),
+            Fields::Unnamed(_) | Fields::Unit => Vec::new(),
+        },
+        _ => {
+            return Err(Error::new_spanned(
+                input,
+                "EjdbDocument can only be derived for structures",
+            ))
+        }
+    };
+
+    let mut indices = Vec::new();
+    for field in fields {
+        let ident = field.ident.as_ref().expect("named field without a name");
+        if let Some(index) = parse_field_attrs(ident, &field.attrs)? {
+            indices.push(index);
+        }
+    }
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let collection = attrs
+        .collection
+        .unwrap_or_else(|| to_snake_case(&name.to_string()));
+
+    let mut options = Vec::new();
+    if let Some(large) = attrs.large {
+        options.push(quote! { .large(#large) });
+    }
+    if let Some(compressed) = attrs.compressed {
+        options.push(quote! { .compressed(#compressed) });
+    }
+    if let Some(records) = attrs.records {
+        options.push(quote! { .records(#records) });
+    }
+    if let Some(cached_records) = attrs.cached_records {
+        options.push(quote! { .cached_records(#cached_records) });
+    }
+
+    let index_calls = indices.iter().map(|index| {
+        let field = &index.field;
+        let kinds = index.kinds.iter().map(|kind| match *kind {
+            IndexKind::String => quote! { .string(true) },
+            IndexKind::CaseInsensitiveString => quote! { .string(false) },
+            IndexKind::Number => quote! { .number() },
+            IndexKind::Array => quote! { .array() },
+        });
+        quote! { coll.index(#field) #(#kinds)* .set()?; }
+    });
+
+    Ok(quote! {
+        impl #impl_generics ::ejdb::EjdbDocument for #name #ty_generics #where_clause {
+            fn collection_name() -> &'static str {
+                #collection
+            }
+
+            fn collection_options() -> ::ejdb::CollectionOptions {
+                ::ejdb::CollectionOptions::default() #(#options)*
+            }
+
+            fn ensure_indices(coll: &::ejdb::Collection) -> ::ejdb::Result<()> {
+                #(#index_calls)*
+                Ok(())
+            }
+        }
+    })
+}
+
+fn ejdb_meta_items(attrs: &[Attribute], name: &str) -> Result<Vec<NestedMeta>, Error> {
+    let mut items = Vec::new();
+    for attr in attrs {
+        if !attr.path.is_ident(name) {
+            continue;
+        }
+        match attr.parse_meta()? {
+            Meta::List(list) => items.extend(list.nested),
+            other => {
+                return Err(Error::new_spanned(
+                    other,
+                    format!("expected #[{}(...)] attribute", name),
+                ))
+            }
+        }
+    }
+    Ok(items)
+}
+
+fn parse_container_attrs(attrs: &[Attribute]) -> Result<ContainerAttrs, Error> {
+    let mut result = ContainerAttrs::default();
+    for item in ejdb_meta_items(attrs, "ejdb")? {
+        match item {
+            NestedMeta::Meta(Meta::Path(ref path)) if path.is_ident("large") => {
+                result.large = Some(true);
+            }
+            NestedMeta::Meta(Meta::Path(ref path)) if path.is_ident("compressed") => {
+                result.compressed = Some(true);
+            }
+            NestedMeta::Meta(Meta::NameValue(ref nv)) => {
+                let key = nv
+                    .path
+                    .get_ident()
+                    .map(|i| i.to_string())
+                    .unwrap_or_default();
+                match (&*key, &nv.lit) {
+                    ("collection", Lit::Str(s)) => result.collection = Some(s.value()),
+                    ("large", Lit::Bool(b)) => result.large = Some(b.value),
+                    ("compressed", Lit::Bool(b)) => result.compressed = Some(b.value),
+                    ("records", Lit::Int(n)) => result.records = Some(n.base10_parse()?),
+                    ("cached_records", Lit::Int(n)) => {
+                        result.cached_records = Some(n.base10_parse()?)
+                    }
+                    _ => return Err(Error::new_spanned(nv, "unknown or invalid ejdb attribute")),
+                }
+            }
+            other => return Err(Error::new(other.span(), "unknown ejdb attribute")),
+        }
+    }
+    Ok(result)
+}
+
+fn parse_field_attrs(
+    ident: &syn::Ident,
+    attrs: &[Attribute],
+) -> Result<Option<FieldIndices>, Error> {
+    let mut field = None;
+    let mut kinds = Vec::new();
+    for item in ejdb_meta_items(attrs, "ejdb")? {
+        match item {
+            NestedMeta::Meta(Meta::NameValue(ref nv)) => {
+                let key = nv
+                    .path
+                    .get_ident()
+                    .map(|i| i.to_string())
+                    .unwrap_or_default();
+                match (&*key, &nv.lit) {
+                    ("field", Lit::Str(s)) => field = Some(s.value()),
+                    ("index", Lit::Str(s)) => kinds.push(match &*s.value() {
+                        "string" => IndexKind::String,
+                        "istring" => IndexKind::CaseInsensitiveString,
+                        "number" => IndexKind::Number,
+                        "array" => IndexKind::Array,
+                        _ => {
+                            return Err(Error::new_spanned(
+                                s,
+                                "index type must be one of \"string\", \"istring\", \"number\" or \"array\"",
+                            ))
+                        }
+                    }),
+                    _ => return Err(Error::new_spanned(nv, "unknown or invalid ejdb attribute")),
+                }
+            }
+            other => return Err(Error::new(other.span(), "unknown ejdb attribute")),
+        }
+    }
+
+    if kinds.is_empty() {
+        return Ok(None);
+    }
+
+    let field = match field {
+        Some(field) => field,
+        None => serde_rename(attrs)?.unwrap_or_else(|| {
+            let name = ident.to_string();
+            match name.strip_prefix("r#") {
+                Some(name) => name.to_owned(),
+                None => name,
+            }
+        }),
+    };
+    Ok(Some(FieldIndices { field, kinds }))
+}
+
+fn serde_rename(attrs: &[Attribute]) -> Result<Option<String>, Error> {
+    for item in ejdb_meta_items(attrs, "serde")? {
+        if let NestedMeta::Meta(Meta::NameValue(ref nv)) = item {
+            if nv.path.is_ident("rename") {
+                if let Lit::Str(ref s) = nv.lit {
+                    return Ok(Some(s.value()));
+                }
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn to_snake_case(name: &str) -> String {
+    let mut result = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() {
+            if i > 0 {
+                result.push('_');
+            }
+            result.extend(c.to_lowercase());
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
@@ -4,6 +4,7 @@ extern crate ejdb;
 use std::env;
 use std::io::Write;
 
+use ejdb::transfer::{ExportFormat, ImportMode};
 use ejdb::Database;
 
 macro_rules! abort {
@@ -13,12 +14,47 @@ macro_rules! abort {
     }}
 }
 
+const USAGE: &'static str =
+    "Usage: ejdb-stat <database> [export <dir> [bson|json] | import <dir> [update|replace]]";
+
 fn main() {
-    let db_path = env::args()
-        .nth(1)
-        .unwrap_or_else(|| abort!(1, "Usage: ejdb-stat <database>"));
+    let args: Vec<String> = env::args().skip(1).collect();
+    let db_path = args.get(0).unwrap_or_else(|| abort!(1, "{}", USAGE));
+
+    let db =
+        Database::open(&**db_path).unwrap_or_else(|e| abort!(1, "Error opening database: {}", e));
 
-    let db = Database::open(db_path).unwrap_or_else(|e| abort!(1, "Error opening database: {}", e));
+    let command = args.get(1).map(|s| &**s);
+    let dir = args.get(2).map(|s| &**s);
+    let option = args.get(3).map(|s| &**s);
+    match (command, dir) {
+        (None, _) => {}
+        (Some("export"), Some(dir)) => {
+            let format = match option {
+                None | Some("bson") => ExportFormat::Bson,
+                Some("json") => ExportFormat::Json,
+                Some(_) => abort!(1, "{}", USAGE),
+            };
+            let report = db
+                .export(dir, Vec::<String>::new(), format)
+                .unwrap_or_else(|e| abort!(1, "Error exporting database: {}", e));
+            println!("Exported:");
+            println!("{}", report);
+        }
+        (Some("import"), Some(dir)) => {
+            let mode = match option {
+                None | Some("update") => ImportMode::Update,
+                Some("replace") => ImportMode::Replace,
+                Some(_) => abort!(1, "{}", USAGE),
+            };
+            let report = db
+                .import(dir, Vec::<String>::new(), mode)
+                .unwrap_or_else(|e| abort!(1, "Error importing database: {}", e));
+            println!("Imported:");
+            println!("{}", report);
+        }
+        _ => abort!(1, "{}", USAGE),
+    }
 
     println!("Metadata:");
     let meta = db
@@ -0,0 +1,366 @@
+//! Asynchronous access to EJDB databases.
+//!
+//! EJDB is a blocking library, and `Collection` objects borrow the database they belong to,
+//! which makes it inconvenient to use them directly from asynchronous code. `AsyncDatabase`
+//! solves this by moving the `Database` object to a dedicated worker thread. All operations
+//! are sent to this thread over a channel and executed there one by one, in the order of
+//! submission; their results are delivered back as futures and streams compatible with any
+//! executor based on the `futures` crate, for example, `tokio`.
+//!
+//! This module is only available when the `async` feature of this crate is enabled.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::thread;
+
+use bson::{oid, Document};
+use futures::channel::{mpsc, oneshot};
+use futures::executor;
+use futures::task::{Context, Poll};
+use futures::{Future, Stream};
+
+use super::open_mode::DatabaseOpenMode;
+use super::query::{Query, QueryHints};
+use super::{Collection, Database};
+use {Error, Result};
+
+type Job = Box<dyn FnOnce(&Database) + Send>;
+
+/// A handle to an EJDB database owned by a dedicated worker thread.
+///
+/// All methods of this structure return immediately; the requested operations are executed
+/// on the worker thread in the order they were submitted, and their results are available
+/// through the returned futures and streams. Collections are identified by their names
+/// and are created on first use, just like with `Database::collection()`.
+///
+/// `AsyncDatabase` is `Send` and `Sync`, and it can be cloned cheaply; all clones submit
+/// their requests to the same worker thread. The worker thread stops and the database is
+/// closed when all handles are dropped and all pending requests are processed.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[macro_use] extern crate ejdb;
+/// # extern crate futures;
+/// use ejdb::AsyncDatabase;
+/// use ejdb::query::{Q, QH};
+/// use futures::executor::block_on;
+/// use futures::StreamExt;
+///
+/// # fn main() {
+/// let db = AsyncDatabase::open("/path/to/db").unwrap();
+/// let id = block_on(db.save("users", bson! { "name" => "Foo" })).unwrap();
+/// let user = block_on(db.load("users", id)).unwrap();
+/// let names: Vec<_> = block_on(
+///     db.query("users", Q.field("name").exists(true), QH.empty()).find().collect()
+/// );
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct AsyncDatabase {
+    jobs: mpsc::UnboundedSender<Job>,
+}
+
+impl AsyncDatabase {
+    /// Opens the specified database with the provided open mode and moves it to a new
+    /// worker thread.
+    ///
+    /// The database is opened on the calling thread, so any errors are reported immediately.
+    ///
+    /// # Failures
+    ///
+    /// Fails in the same cases as `Database::open_with_mode()` or if the worker thread can't
+    /// be started.
+    pub fn open_with_mode<P: Into<Vec<u8>>>(
+        path: P,
+        open_mode: DatabaseOpenMode,
+    ) -> Result<AsyncDatabase> {
+        Database::open_with_mode(path, open_mode).and_then(AsyncDatabase::new)
+    }
+
+    /// Opens the specified database with the default open mode and moves it to a new
+    /// worker thread.
+    ///
+    /// # Failures
+    ///
+    /// Fails in the same cases as `Database::open()` or if the worker thread can't
+    /// be started.
+    pub fn open<P: Into<Vec<u8>>>(path: P) -> Result<AsyncDatabase> {
+        Database::open(path).and_then(AsyncDatabase::new)
+    }
+
+    /// Moves the provided database to a new worker thread.
+    ///
+    /// # Failures
+    ///
+    /// Returns an error if the worker thread can't be started.
+    pub fn new(db: Database) -> Result<AsyncDatabase> {
+        let (jobs, receiver) = mpsc::unbounded::<Job>();
+        try!(thread::Builder::new()
+            .name("ejdb-worker".into())
+            .spawn(move || {
+                for job in executor::block_on_stream(receiver) {
+                    // a panicking job drops its result sender, so its future will fail,
+                    // but the worker must keep serving other requests
+                    let _ = panic::catch_unwind(AssertUnwindSafe(|| job(&db)));
+                }
+            }));
+        Ok(AsyncDatabase { jobs: jobs })
+    }
+
+    /// Runs an arbitrary function with the database on the worker thread.
+    ///
+    /// This is the most generic method of `AsyncDatabase`; all other methods are implemented
+    /// with it. The function must not block for long, because all other requests wait
+    /// until it completes.
+    ///
+    /// # Failures
+    ///
+    /// The returned future resolves to the result of the function, or to an error if the worker
+    /// thread has stopped or the function has panicked.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # extern crate ejdb;
+    /// # extern crate futures;
+    /// # use ejdb::AsyncDatabase;
+    /// # fn main() {
+    /// let db = AsyncDatabase::open("/path/to/db").unwrap();
+    /// let names = futures::executor::block_on(db.execute(|db| db.collection_names())).unwrap();
+    /// # }
+    /// ```
+    pub fn execute<F, T>(&self, f: F) -> AsyncResult<T>
+    where
+        F: FnOnce(&Database) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let (sender, receiver) = oneshot::channel();
+        let job: Job = Box::new(move |db: &Database| {
+            let _ = sender.send(f(db));
+        });
+        // if the worker thread is gone, the job is dropped along with the result sender,
+        // and the future resolves to an error
+        let _ = self.jobs.unbounded_send(job);
+        AsyncResult { receiver: receiver }
+    }
+
+    /// Runs an arbitrary function with the specified collection on the worker thread.
+    ///
+    /// The collection is created if it does not exist.
+    ///
+    /// # Failures
+    ///
+    /// The returned future fails in the same cases as `execute()` or if the collection
+    /// can't be obtained.
+    pub fn with_collection<S, F, T>(&self, coll: S, f: F) -> AsyncResult<T>
+    where
+        S: Into<String>,
+        F: FnOnce(&Collection) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let name = coll.into();
+        self.execute(move |db| db.collection(name).and_then(|coll| f(&coll)))
+    }
+
+    /// Saves the document to the specified collection.
+    ///
+    /// See `Collection::save()` for more information.
+    pub fn save<S: Into<String>>(&self, coll: S, doc: Document) -> AsyncResult<oid::ObjectId> {
+        self.with_collection(coll, move |coll| coll.save(doc))
+    }
+
+    /// Loads the document from the specified collection by its id.
+    ///
+    /// See `Collection::load()` for more information.
+    pub fn load<S: Into<String>>(
+        &self,
+        coll: S,
+        id: oid::ObjectId,
+    ) -> AsyncResult<Option<Document>> {
+        self.with_collection(coll, move |coll| coll.load(&id))
+    }
+
+    /// Prepares a query over the specified collection.
+    ///
+    /// The query is not sent to the worker thread until one of the methods of the returned
+    /// object is called. See `Collection::query()` for more information.
+    pub fn query<S: Into<String>>(&self, coll: S, query: Query, hints: QueryHints) -> AsyncQuery {
+        AsyncQuery {
+            db: self.clone(),
+            coll: coll.into(),
+            query: query,
+            hints: hints,
+        }
+    }
+
+    /// Runs the provided function inside a transaction over the specified collection.
+    ///
+    /// The transaction is committed if the function returns `Ok`, and it is aborted if
+    /// the function returns an error or panics. No other requests are executed while
+    /// the function is running.
+    ///
+    /// # Failures
+    ///
+    /// The returned future resolves to an error if the transaction can't be started or
+    /// committed, or to the error returned by the function.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # #[macro_use] extern crate ejdb;
+    /// # extern crate futures;
+    /// # use ejdb::AsyncDatabase;
+    /// # fn main() {
+    /// let db = AsyncDatabase::open("/path/to/db").unwrap();
+    /// let ids = db.transaction("users", |coll| {
+    ///     let a = coll.save(bson! { "name" => "Foo" })?;
+    ///     let b = coll.save(bson! { "name" => "Bar" })?;
+    ///     Ok((a, b))
+    /// });
+    /// let (a, b) = futures::executor::block_on(ids).unwrap();
+    /// # }
+    /// ```
+    pub fn transaction<S, F, T>(&self, coll: S, f: F) -> AsyncResult<T>
+    where
+        S: Into<String>,
+        F: FnOnce(&Collection) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        self.with_collection(coll, move |coll| {
+            let tx = try!(coll.begin_transaction());
+            // the transaction is aborted when dropped, including unwinding
+            let result = try!(f(coll));
+            try!(tx.commit());
+            Ok(result)
+        })
+    }
+}
+
+/// A query prepared for execution on the worker thread of `AsyncDatabase`.
+///
+/// This is an asynchronous counterpart of `PreparedQuery`. Objects of this structure
+/// are returned by `AsyncDatabase::query()` method.
+pub struct AsyncQuery {
+    db: AsyncDatabase,
+    coll: String,
+    query: Query,
+    hints: QueryHints,
+}
+
+impl AsyncQuery {
+    /// Executes the query, returning a stream of all documents matching it.
+    ///
+    /// The whole result of an EJDB query is computed at once, so the documents are sent
+    /// to the stream by the worker thread as soon as the query is executed, without waiting
+    /// for the stream to be polled. If the query fails, the stream yields a single error.
+    ///
+    /// See `PreparedQuery::find()` for more information.
+    pub fn find(self) -> DocumentStream {
+        let (sender, receiver) = mpsc::unbounded();
+        let AsyncQuery {
+            db,
+            coll,
+            query,
+            hints,
+        } = self;
+        let _ = db.execute(move |db| {
+            let result = db
+                .collection(coll)
+                .and_then(|coll| coll.query(query, hints).find());
+            match result {
+                Ok(result) => {
+                    for doc in result {
+                        if sender.unbounded_send(doc).is_err() {
+                            break; // the stream was dropped
+                        }
+                    }
+                }
+                Err(e) => {
+                    let _ = sender.unbounded_send(Err(e));
+                }
+            }
+            Ok(())
+        });
+        DocumentStream { receiver: receiver }
+    }
+
+    /// Executes the query, returning the first document matching it, if any.
+    ///
+    /// See `PreparedQuery::find_one()` for more information.
+    pub fn find_one(self) -> AsyncResult<Option<Document>> {
+        let AsyncQuery {
+            db,
+            coll,
+            query,
+            hints,
+        } = self;
+        db.with_collection(coll, move |coll| coll.query(query, hints).find_one())
+    }
+
+    /// Executes the query, returning the number of matching records.
+    ///
+    /// See `PreparedQuery::count()` for more information.
+    pub fn count(self) -> AsyncResult<u32> {
+        let AsyncQuery {
+            db,
+            coll,
+            query,
+            hints,
+        } = self;
+        db.with_collection(coll, move |coll| coll.query(query, hints).count())
+    }
+
+    /// Executes the update query, returning the number of affected records.
+    ///
+    /// See `PreparedQuery::update()` for more information.
+    pub fn update(self) -> AsyncResult<u32> {
+        let AsyncQuery {
+            db,
+            coll,
+            query,
+            hints,
+        } = self;
+        db.with_collection(coll, move |coll| coll.query(query, hints).update())
+    }
+}
+
+/// A future which resolves to the result of an operation executed by `AsyncDatabase`.
+///
+/// If the worker thread stops before the operation is completed, for example, because
+/// the operation has panicked, the future resolves to an error.
+#[must_use = "futures do nothing unless polled"]
+pub struct AsyncResult<T> {
+    receiver: oneshot::Receiver<Result<T>>,
+}
+
+impl<T> Future for AsyncResult<T> {
+    type Output = Result<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<T>> {
+        match Pin::new(&mut self.receiver).poll(cx) {
+            Poll::Ready(Ok(result)) => Poll::Ready(result),
+            Poll::Ready(Err(_)) => Poll::Ready(Err(Error::from(
+                "database worker thread has stopped before completing the operation",
+            ))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// A stream of documents returned by `AsyncQuery::find()`.
+///
+/// Each item of this stream is either a document or an error; the stream ends when all
+/// documents matching the query are received.
+#[must_use = "streams do nothing unless polled"]
+pub struct DocumentStream {
+    receiver: mpsc::UnboundedReceiver<Result<Document>>,
+}
+
+impl Stream for DocumentStream {
+    type Item = Result<Document>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Document>>> {
+        Pin::new(&mut self.receiver).poll_next(cx)
+    }
+}
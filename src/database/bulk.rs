@@ -0,0 +1,275 @@
+//! Bulk writes executed in a single transaction.
+//!
+//! `BulkWrite` collects a sequence of write operations over a collection and executes them
+//! inside one transaction. Unlike `Collection::save_all()`, a failed operation does not hide
+//! the results of the other ones: every operation gets its own outcome in the returned
+//! `BulkResult`.
+
+use bson::{oid, Bson, Document};
+use ejdb_sys;
+
+use super::query::{self, Q, QH};
+use super::Collection;
+use {Error, Result};
+
+impl<'db> Collection<'db> {
+    /// Returns a builder for a bulk write over this collection.
+    ///
+    /// See `BulkWrite` documentation for more information.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # #[macro_use] extern crate ejdb;
+    /// # use ejdb::Database;
+    /// use ejdb::query::{Q, QH};
+    ///
+    /// # fn main() {
+    /// let db = Database::open("/path/to/db").unwrap();
+    /// let coll = db.collection("some_collection").unwrap();
+    /// # let id = ejdb::bson::oid::ObjectId::new().unwrap();
+    /// let result = coll.bulk_write()
+    ///     .insert(bson! { "name" => "Foo", "count" => 1 })
+    ///     .update(Q.field("name").eq("Bar").inc("count", 1), QH.empty())
+    ///     .remove(id)
+    ///     .execute()
+    ///     .unwrap();
+    /// assert!(result.committed);
+    /// # }
+    /// ```
+    #[inline]
+    pub fn bulk_write(&self) -> BulkWrite {
+        BulkWrite {
+            coll: self,
+            ops: Vec::new(),
+            best_effort: false,
+        }
+    }
+}
+
+enum BulkOp {
+    Insert(Document),
+    Replace(Document),
+    Remove(oid::ObjectId),
+    Update(query::Query, query::QueryHints),
+}
+
+/// A builder for a sequence of write operations executed in a single transaction.
+///
+/// Operations are executed in the order they were added. By default, a bulk write is
+/// all-or-nothing: the first failed operation aborts the transaction, and the remaining
+/// operations are skipped. In best-effort mode, set with `best_effort()`, failed
+/// operations are recorded and the rest are still executed and committed.
+///
+/// EJDB transactions have no savepoints, so a single failed operation can't be rolled back
+/// without rolling back the whole transaction. In best-effort mode changes made by a failed
+/// operation before its failure are committed together with the other operations; this
+/// matters for update queries, which may fail after some of the matching records have
+/// already been updated.
+///
+/// `BulkWrite` is created with `Collection::bulk_write()` method.
+pub struct BulkWrite<'coll, 'db: 'coll> {
+    coll: &'coll Collection<'db>,
+    ops: Vec<BulkOp>,
+    best_effort: bool,
+}
+
+impl<'coll, 'db> BulkWrite<'coll, 'db> {
+    /// Adds an insertion of a new document.
+    ///
+    /// If the document has no `_id` field, it will be generated. If it has one and
+    /// a document with this id already exists, the operation fails with
+    /// `ErrorKind::RecordExists` error. The existence check is an additional count query
+    /// by `_id`, so the existing document is not decoded; documents without `_id` are saved
+    /// without it.
+    pub fn insert<D: Into<Document>>(mut self, doc: D) -> Self {
+        self.ops.push(BulkOp::Insert(doc.into()));
+        self
+    }
+
+    /// Adds a replacement of a document by its `_id` field.
+    ///
+    /// The document is saved as if by `Collection::save()`, so it is created if it
+    /// does not exist. The operation fails if the document has no `_id` field.
+    pub fn replace<D: Into<Document>>(mut self, doc: D) -> Self {
+        self.ops.push(BulkOp::Replace(doc.into()));
+        self
+    }
+
+    /// Adds a removal of a document by its id.
+    pub fn remove(mut self, id: oid::ObjectId) -> Self {
+        self.ops.push(BulkOp::Remove(id));
+        self
+    }
+
+    /// Adds an update query.
+    ///
+    /// The query is executed as if by `PreparedQuery::update()`.
+    pub fn update<Q, H>(mut self, query: Q, hints: H) -> Self
+    where
+        Q: Into<query::Query>,
+        H: Into<query::QueryHints>,
+    {
+        self.ops.push(BulkOp::Update(query.into(), hints.into()));
+        self
+    }
+
+    /// Makes the bulk write all-or-nothing. This is the default.
+    #[inline]
+    pub fn all_or_nothing(mut self) -> Self {
+        self.best_effort = false;
+        self
+    }
+
+    /// Makes the bulk write best-effort: failed operations do not prevent other operations
+    /// from being executed and committed.
+    ///
+    /// Partial changes made by a failed operation are committed as well; see `BulkWrite`
+    /// documentation for details.
+    #[inline]
+    pub fn best_effort(mut self) -> Self {
+        self.best_effort = true;
+        self
+    }
+
+    /// Returns the number of operations in this bulk write.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    /// Returns `true` if this bulk write contains no operations.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Executes all operations in a single transaction.
+    ///
+    /// Failures of individual operations are reported in the outcomes of the returned
+    /// `BulkResult`. In all-or-nothing mode, the first failure aborts the transaction;
+    /// `BulkResult::committed` is `false` in this case, and none of the operations
+    /// have any effect.
+    ///
+    /// # Failures
+    ///
+    /// Returns an error if the transaction cannot be started, committed or aborted.
+    pub fn execute(self) -> Result<BulkResult> {
+        let BulkWrite {
+            coll,
+            ops,
+            best_effort,
+        } = self;
+
+        let tx = try!(coll.begin_transaction());
+        let mut outcomes = Vec::with_capacity(ops.len());
+        let mut failed = false;
+        for op in ops {
+            if failed && !best_effort {
+                outcomes.push(BulkOutcome::Skipped);
+                continue;
+            }
+            let outcome = match execute_op(coll, op) {
+                Ok(outcome) => outcome,
+                Err(e) => {
+                    failed = true;
+                    BulkOutcome::Failed(e)
+                }
+            };
+            outcomes.push(outcome);
+        }
+
+        let committed = if failed && !best_effort {
+            try!(tx.abort());
+            false
+        } else {
+            try!(tx.commit());
+            true
+        };
+        Ok(BulkResult {
+            outcomes: outcomes,
+            committed: committed,
+        })
+    }
+}
+
+fn execute_op(coll: &Collection, op: BulkOp) -> Result<BulkOutcome> {
+    match op {
+        BulkOp::Insert(doc) => {
+            if let Some(&Bson::ObjectId(ref id)) = doc.get("_id") {
+                if try!(coll.query(Q.id(id.clone()), QH.empty()).count()) > 0 {
+                    return Err(Error::from_code(
+                        ejdb_sys::TCEKEEP as i32,
+                        "error inserting BSON document",
+                    ));
+                }
+            }
+            coll.save(&doc).map(BulkOutcome::Saved)
+        }
+        BulkOp::Replace(doc) => match doc.get("_id") {
+            Some(&Bson::ObjectId(_)) => coll.save(&doc).map(BulkOutcome::Saved),
+            _ => Err("replaced document must have an object id in _id field".into()),
+        },
+        BulkOp::Remove(id) => coll
+            .remove(&id)
+            .map(|existed| BulkOutcome::Affected(existed as u32)),
+        BulkOp::Update(query, hints) => {
+            coll.query(query, hints).update().map(BulkOutcome::Affected)
+        }
+    }
+}
+
+/// An outcome of a single operation of a bulk write.
+#[derive(Debug)]
+pub enum BulkOutcome {
+    /// A document was inserted or replaced; contains its id.
+    Saved(oid::ObjectId),
+    /// A removal or an update was executed; contains the number of affected records.
+    Affected(u32),
+    /// The operation has failed.
+    Failed(Error),
+    /// The operation was not executed because an earlier operation has failed in
+    /// all-or-nothing mode.
+    Skipped,
+}
+
+impl BulkOutcome {
+    /// Returns `true` if the operation has failed.
+    #[inline]
+    pub fn is_failed(&self) -> bool {
+        match *self {
+            BulkOutcome::Failed(_) => true,
+            _ => false,
+        }
+    }
+}
+
+/// The result of a bulk write returned by `BulkWrite::execute()`.
+#[derive(Debug)]
+pub struct BulkResult {
+    /// Outcomes of all operations, in the order they were added to the bulk write.
+    pub outcomes: Vec<BulkOutcome>,
+    /// Whether the transaction has been committed.
+    ///
+    /// This is `false` if some operation has failed in all-or-nothing mode.
+    pub committed: bool,
+}
+
+impl BulkResult {
+    /// Returns `true` if the transaction has been committed and no operation has failed.
+    pub fn is_success(&self) -> bool {
+        self.committed && !self.outcomes.iter().any(BulkOutcome::is_failed)
+    }
+
+    /// Returns the errors of failed operations together with the indices of the operations.
+    pub fn errors(&self) -> Vec<(usize, &Error)> {
+        self.outcomes
+            .iter()
+            .enumerate()
+            .filter_map(|(i, outcome)| match *outcome {
+                BulkOutcome::Failed(ref e) => Some((i, e)),
+                _ => None,
+            })
+            .collect()
+    }
+}
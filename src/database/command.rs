@@ -0,0 +1,240 @@
+//! Access to EJDB command interface.
+//!
+//! EJDB provides a generic entry point for running maintenance commands, like export
+//! and import of collections, which accepts a BSON document describing the command and
+//! returns another BSON document with the results. `Database::command()` method provides
+//! a raw access to this interface, while `Command` and `CommandResponse` enums provide
+//! a typed one.
+
+use std::borrow::Borrow;
+
+use bson::{Bson, Document};
+use ejdb_sys;
+
+use super::transfer::{ExportFormat, ImportMode};
+use super::Database;
+use ejdb_bson::EjdbBsonDocument;
+use {Error, Result};
+
+impl Database {
+    /// Executes the provided EJDB command, returning the response document.
+    ///
+    /// This is a low-level method which passes the command document to EJDB as is; see
+    /// `Command` enum for a typed alternative.
+    ///
+    /// # Failures
+    ///
+    /// Returns an error if the command document can't be converted to EJDB representation,
+    /// if the response can't be converted back or if EJDB reports an error during the
+    /// command execution.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # #[macro_use] extern crate ejdb;
+    /// # use ejdb::Database;
+    /// # fn main() {
+    /// let db = Database::open("/path/to/db").unwrap();
+    /// let response = db.command(bson! { "ping" => {} }).unwrap();
+    /// // response is ejdb::bson::Document
+    /// # }
+    /// ```
+    pub fn command<D: Borrow<Document>>(&self, cmd: D) -> Result<Document> {
+        let cmd = cmd.borrow();
+        let mut ejdb_cmd = try!(EjdbBsonDocument::from_bson(cmd));
+        let result = unsafe { ejdb_sys::ejdbcommand(self.0, ejdb_cmd.as_raw_mut()) };
+        if result.is_null() {
+            return self.last_error("error executing command");
+        }
+
+        let response = try!(unsafe { EjdbBsonDocument::from_ptr(result) }.to_bson());
+        let error = match (response.get("errorCode"), response.get("error")) {
+            (Some(&Bson::I32(code)), Some(&Bson::String(ref msg))) => Some(Error::from_code(
+                code,
+                format!("error executing command: {}", msg),
+            )),
+            (_, Some(&Bson::String(ref msg))) => {
+                Some(format!("error executing command: {}", msg).into())
+            }
+            _ => None,
+        };
+        if let Some(e) = error {
+            return Err(e);
+        }
+
+        // the import command may replace collections
+        if cmd.contains_key("import") {
+            self.invalidate_collections();
+        }
+        Ok(response)
+    }
+}
+
+/// A typed EJDB command.
+///
+/// Commands can be converted to BSON documents accepted by `Database::command()` method
+/// or executed directly with `Command::execute()` method.
+///
+/// # Example
+///
+/// ```no_run
+/// # use ejdb::Database;
+/// use ejdb::command::Command;
+/// use ejdb::transfer::ExportFormat;
+///
+/// let db = Database::open("/path/to/db").unwrap();
+/// let response = Command::Export {
+///     path: "/path/to/backup".into(),
+///     collections: vec!["some_collection".into()],
+///     format: ExportFormat::Json,
+/// }.execute(&db).unwrap();
+/// println!("{}", response.log().unwrap_or(""));
+/// ```
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum Command {
+    /// Exports collections into a directory, like `Database::export()`.
+    ///
+    /// An empty collections list means all collections.
+    Export {
+        /// The directory to export the collections into.
+        path: String,
+        /// Names of the collections to export.
+        collections: Vec<String>,
+        /// The format of exported files.
+        format: ExportFormat,
+    },
+    /// Imports collections from a directory, like `Database::import()`.
+    ///
+    /// An empty collections list means all collections found in the directory.
+    Import {
+        /// The directory to import the collections from.
+        path: String,
+        /// Names of the collections to import.
+        collections: Vec<String>,
+        /// The way of handling existing collections.
+        mode: ImportMode,
+    },
+    /// Checks that the database is accessible.
+    Ping,
+}
+
+impl Command {
+    /// Converts this command to a BSON document.
+    pub fn into_bson(self) -> Document {
+        match self {
+            Command::Export {
+                path,
+                collections,
+                format,
+            } => bson! {
+                "export" => {
+                    "path" => path,
+                    "cnames" => (collections.into_iter().map(Bson::from).collect::<Vec<_>>()),
+                    "mode" => (format.flags() as i32)
+                }
+            },
+            Command::Import {
+                path,
+                collections,
+                mode,
+            } => bson! {
+                "import" => {
+                    "path" => path,
+                    "cnames" => (collections.into_iter().map(Bson::from).collect::<Vec<_>>()),
+                    "mode" => (mode.flags() as i32)
+                }
+            },
+            Command::Ping => bson! { "ping" => {} },
+        }
+    }
+
+    /// Invokes `db.command(cmd)` with this command, returning a typed response.
+    ///
+    /// # Failures
+    ///
+    /// Fails in the same cases as `Database::command()`.
+    pub fn execute(self, db: &Database) -> Result<CommandResponse> {
+        let make_response: fn(TransferResponse) -> CommandResponse = match self {
+            Command::Export { .. } => CommandResponse::Export,
+            Command::Import { .. } => CommandResponse::Import,
+            Command::Ping => {
+                try!(db.command(self.into_bson()));
+                return Ok(CommandResponse::Ping);
+            }
+        };
+        let response = try!(db.command(self.into_bson()));
+        // EJDB always returns the log for transfer commands, but its absence is harmless
+        let log = response.get_str("log").unwrap_or("").to_owned();
+        Ok(make_response(TransferResponse { log: log }))
+    }
+}
+
+impl From<Command> for Document {
+    #[inline]
+    fn from(cmd: Command) -> Document {
+        cmd.into_bson()
+    }
+}
+
+/// Represents a response of a successfully executed typed EJDB command.
+///
+/// Each variant corresponds to the `Command` variant with the same name.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum CommandResponse {
+    /// A response of `Command::Export`.
+    Export(TransferResponse),
+    /// A response of `Command::Import`.
+    Import(TransferResponse),
+    /// A response of `Command::Ping`.
+    Ping,
+}
+
+impl CommandResponse {
+    /// Returns the log of the command execution, if the command provides one.
+    pub fn log(&self) -> Option<&str> {
+        match *self {
+            CommandResponse::Export(ref r) | CommandResponse::Import(ref r) => Some(r.log()),
+            CommandResponse::Ping => None,
+        }
+    }
+}
+
+/// A response of the export and import commands.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct TransferResponse {
+    log: String,
+}
+
+impl TransferResponse {
+    /// Returns the log of the export or import operation written by EJDB.
+    #[inline]
+    pub fn log(&self) -> &str {
+        &self.log
+    }
+
+    /// Consumes the response object, returning the log of the operation.
+    #[inline]
+    pub fn into_log(self) -> String {
+        self.log
+    }
+}
+
+#[test]
+fn test_command_into_bson() {
+    assert_eq!(Command::Ping.into_bson(), bson! { "ping" => {} });
+    assert_eq!(
+        Command::Import {
+            path: "/tmp/dump".into(),
+            collections: vec!["a".into(), "b".into()],
+            mode: ImportMode::Replace,
+        }
+        .into_bson(),
+        bson! {
+            "import" => {
+                "path" => "/tmp/dump",
+                "cnames" => ["a", "b"],
+                "mode" => (ejdb_sys::JBIMPORTREPLACE as i32)
+            }
+        }
+    );
+}
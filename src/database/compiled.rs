@@ -0,0 +1,290 @@
+//! Query templates with placeholders.
+//!
+//! `CompiledQuery`, created with `Collection::compile()`, keeps a query and its hints which
+//! may contain placeholders (see `query::Param`) instead of values. The placeholders are bound
+//! to values with `CompiledQuery::bind()`, and the query can then be executed any number
+//! of times.
+//!
+//! # Limitations
+//!
+//! EJDB can't change values inside an existing query object. Binding a placeholder to a new
+//! value makes the next execution substitute all bound values into a copy of the whole query
+//! template, encode it and create a new native query object, which is the same amount of work
+//! as executing a `PreparedQuery`. The native query object is only kept between executions
+//! which use the same values.
+//!
+//! Compiled queries support neither additional queries joined with OR
+//! (`PreparedQuery::or_query()`) nor logging (`PreparedQuery::log_output()`), and they are
+//! not validated before execution; `Query::validate()` can be used before compilation instead.
+
+use std::borrow::Borrow;
+
+use bson::{Bson, Document};
+use ejdb_sys;
+
+use super::query::{self, PARAM_KEY};
+use super::{Collection, NativeQuery, QueryResult};
+use Result;
+
+impl<'db> Collection<'db> {
+    /// Creates a query template over this collection from the provided query and hints.
+    ///
+    /// The query and the hints may contain placeholders (see `query::Param`), whose values
+    /// are bound before execution with `CompiledQuery::bind()`. See the module documentation
+    /// for the costs of rebinding.
+    ///
+    /// # Failures
+    ///
+    /// If the query has no placeholders, the native query object is created immediately,
+    /// and an error is returned if this is not possible, e.g. if the query is invalid.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use ejdb::Database;
+    /// use ejdb::query::{Param, Q, QH};
+    ///
+    /// let db = Database::open("/path/to/db").unwrap();
+    /// let coll = db.collection("some_collection").unwrap();
+    /// let mut query = coll.compile(
+    ///     Q.field("age").between(Param("min"), Param("max")),
+    ///     QH.order_by("name").asc(),
+    /// ).unwrap();
+    ///
+    /// for &(min, max) in &[(18, 25), (26, 35), (36, 50)] {
+    ///     let result = query.bind("min", min).bind("max", max).find().unwrap();
+    ///     // process the result
+    /// }
+    /// ```
+    pub fn compile<Q, H>(&self, query: Q, hints: H) -> Result<CompiledQuery>
+    where
+        Q: Borrow<query::Query>,
+        H: Borrow<query::QueryHints>,
+    {
+        let query = query.borrow().as_bson().clone();
+        let hints = hints.borrow().as_bson().clone();
+
+        let mut params = Vec::new();
+        collect_params(&query, &mut params);
+        collect_params(&hints, &mut params);
+
+        let mut compiled = CompiledQuery {
+            coll: self,
+            query: query,
+            hints: hints,
+            params: params,
+            bound: Document::new(),
+            native: None,
+        };
+        if compiled.params.is_empty() {
+            try!(compiled.native_query());
+        }
+        Ok(compiled)
+    }
+}
+
+/// A query template with placeholders over a collection.
+///
+/// This structure keeps a native EJDB query object between executions with the same
+/// parameter values. When a parameter is bound to a new value, the query is recreated
+/// with the new values substituted on the next execution, because EJDB does not support
+/// changing values in an existing query object. See the module documentation for
+/// the costs and limitations of compiled queries.
+///
+/// `CompiledQuery` is created with `Collection::compile()` method.
+pub struct CompiledQuery<'coll, 'db: 'coll> {
+    coll: &'coll Collection<'db>,
+    query: Document,
+    hints: Document,
+    params: Vec<String>,
+    bound: Document,
+    native: Option<NativeQuery>,
+}
+
+impl<'coll, 'db> CompiledQuery<'coll, 'db> {
+    /// Returns the names of all placeholders in the query, in the order of their appearance.
+    #[inline]
+    pub fn params(&self) -> &[String] {
+        &self.params
+    }
+
+    /// Binds a value to the placeholder with the given name.
+    ///
+    /// Values of the placeholders are retained between executions. Binding a value
+    /// to a name which does not appear in the query, or the value the placeholder is
+    /// already bound to, has no effect; otherwise the native query object is recreated
+    /// on the next execution.
+    pub fn bind<S: Into<String>, V: Into<Bson>>(&mut self, name: S, value: V) -> &mut Self {
+        let name = name.into();
+        if !self.params.contains(&name) {
+            return self;
+        }
+        let value = value.into();
+        if self.bound.get(&name) != Some(&value) {
+            self.bound.insert(name, value);
+            self.native = None;
+        }
+        self
+    }
+
+    /// Executes the query, returning the number of affected records.
+    ///
+    /// See `PreparedQuery::count()` for more information.
+    ///
+    /// # Failures
+    ///
+    /// Fails in the same cases as `PreparedQuery::count()` or if some of the placeholders
+    /// are not bound.
+    pub fn count(&mut self) -> Result<u32> {
+        self.execute(ejdb_sys::JBQRYCOUNT).map(|(_, n)| n)
+    }
+
+    /// Executes the query which is expected to update records, returning the number
+    /// of updated records.
+    ///
+    /// See `PreparedQuery::update()` for more information.
+    ///
+    /// # Failures
+    ///
+    /// Fails in the same cases as `PreparedQuery::update()` or if some of the placeholders
+    /// are not bound.
+    pub fn update(&mut self) -> Result<u32> {
+        self.execute(ejdb_sys::JBQRYCOUNT).map(|(_, n)| n)
+    }
+
+    /// Executes the query, returning the first matched element if it is available.
+    ///
+    /// See `PreparedQuery::find_one()` for more information.
+    ///
+    /// # Failures
+    ///
+    /// Fails in the same cases as `PreparedQuery::find_one()` or if some of the placeholders
+    /// are not bound.
+    pub fn find_one(&mut self) -> Result<Option<Document>> {
+        self.execute(ejdb_sys::JBQRYFINDONE)
+            .map(|(r, _)| QueryResult::new(r))
+            .and_then(|qr| match qr.into_iter().next() {
+                Some(r) => r.map(Some),
+                None => Ok(None),
+            })
+    }
+
+    /// Executes the query, returning an iterator of all documents matching the query.
+    ///
+    /// See `PreparedQuery::find()` for more information.
+    ///
+    /// # Failures
+    ///
+    /// Fails in the same cases as `PreparedQuery::find()` or if some of the placeholders
+    /// are not bound.
+    pub fn find(&mut self) -> Result<QueryResult> {
+        self.execute(0).map(|(r, _)| QueryResult::new(r))
+    }
+
+    fn execute(&mut self, flags: u32) -> Result<(ejdb_sys::EJQRESULT, u32)> {
+        let coll = self.coll;
+        let query = try!(self.native_query());
+        query.execute(coll, flags, None)
+    }
+
+    fn native_query(&mut self) -> Result<&NativeQuery> {
+        if self.native.is_none() {
+            let query = try!(substitute(&self.query, &self.bound));
+            let hints = try!(substitute(&self.hints, &self.bound));
+            self.native = Some(try!(NativeQuery::new(self.coll.db, &query, &hints, &[])));
+        }
+        Ok(self.native.as_ref().unwrap())
+    }
+}
+
+fn param_name(doc: &Document) -> Option<&str> {
+    if doc.len() == 1 {
+        doc.get_str(PARAM_KEY).ok()
+    } else {
+        None
+    }
+}
+
+fn collect_params(doc: &Document, params: &mut Vec<String>) {
+    fn collect_value(value: &Bson, params: &mut Vec<String>) {
+        match *value {
+            Bson::Document(ref doc) => match param_name(doc) {
+                Some(name) => {
+                    if !params.iter().any(|p| p == name) {
+                        params.push(name.into());
+                    }
+                }
+                None => collect_params(doc, params),
+            },
+            Bson::Array(ref arr) => {
+                for value in arr {
+                    collect_value(value, params);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for (_, value) in doc {
+        collect_value(value, params);
+    }
+}
+
+fn substitute(doc: &Document, bound: &Document) -> Result<Document> {
+    fn substitute_value(value: &Bson, bound: &Document) -> Result<Bson> {
+        Ok(match *value {
+            Bson::Document(ref doc) => match param_name(doc) {
+                Some(name) => match bound.get(name) {
+                    Some(value) => value.clone(),
+                    None => return Err(format!("query parameter {} is not bound", name).into()),
+                },
+                None => Bson::Document(try!(substitute(doc, bound))),
+            },
+            Bson::Array(ref arr) => {
+                let mut result = Vec::with_capacity(arr.len());
+                for value in arr {
+                    result.push(try!(substitute_value(value, bound)));
+                }
+                Bson::Array(result)
+            }
+            ref other => other.clone(),
+        })
+    }
+
+    let mut result = Document::new();
+    for (key, value) in doc {
+        result.insert(key.clone(), try!(substitute_value(value, bound)));
+    }
+    Ok(result)
+}
+
+#[test]
+fn test_substitute_params() {
+    use super::query::{Param, Q};
+
+    let q = Q
+        .field("age")
+        .between(Param("min"), Param("max"))
+        .field("tags")
+        .contained_in(vec![Bson::from(Param("tag")), "x".into()])
+        .field("name")
+        .eq(Param("min"))
+        .into_bson();
+
+    let mut params = Vec::new();
+    collect_params(&q, &mut params);
+    assert_eq!(params, vec!["min", "max", "tag"]);
+
+    let bound = bson! { "min" => 18, "max" => 25, "tag" => "a" };
+    assert_eq!(
+        substitute(&q, &bound).unwrap(),
+        bson! {
+            "age" => { "$bt" => [18, 25] },
+            "tags" => { "$in" => ["a", "x"] },
+            "name" => 18
+        }
+    );
+
+    let err = substitute(&q, &bson! { "min" => 18 }).unwrap_err();
+    assert_eq!(err.to_string(), "query parameter max is not bound");
+}
@@ -0,0 +1,93 @@
+use super::{Collection, CollectionOptions, Database};
+use Result;
+
+/// A document model which declares its collection, collection options and indices.
+///
+/// Implementing this trait allows creating and configuring the collection for a model
+/// with a single `Database::ensure()` call. While it can be implemented manually, it is
+/// usually derived with `#[derive(EjdbDocument)]`, which is available when the `derive`
+/// feature of this crate is enabled:
+///
+/// ```ignore
+/// #[macro_use] extern crate ejdb;
+///
+/// #[derive(EjdbDocument)]
+/// #[ejdb(collection = "users", compressed, records = 1_000_000)]
+/// struct User {
+///     #[ejdb(index = "string")]
+///     name: String,
+///     #[ejdb(index = "number")]
+///     age: i32,
+/// }
+/// ```
+///
+/// See `ejdb-derive` crate documentation for the full list of supported attributes.
+///
+/// # Example
+///
+/// A manual implementation equivalent to the derived one above:
+///
+/// ```no_run
+/// use ejdb::{Collection, CollectionOptions, Database, EjdbDocument};
+///
+/// struct User {
+///     name: String,
+///     age: i32,
+/// }
+///
+/// impl EjdbDocument for User {
+///     fn collection_name() -> &'static str {
+///         "users"
+///     }
+///
+///     fn collection_options() -> CollectionOptions {
+///         CollectionOptions::default().compressed(true).records(1_000_000)
+///     }
+///
+///     fn ensure_indices(coll: &Collection) -> ejdb::Result<()> {
+///         coll.index("name").string(true).set()?;
+///         coll.index("age").number().set()?;
+///         Ok(())
+///     }
+/// }
+///
+/// let db = Database::open("/path/to/db").unwrap();
+/// let users = db.ensure::<User>().unwrap();
+/// ```
+pub trait EjdbDocument {
+    /// Returns the name of the collection which stores documents of this type.
+    fn collection_name() -> &'static str;
+
+    /// Returns the options used when the collection is created.
+    fn collection_options() -> CollectionOptions {
+        CollectionOptions::default()
+    }
+
+    /// Creates the indices declared for this type in the provided collection.
+    fn ensure_indices(_coll: &Collection) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Database {
+    /// Creates the collection for the given document type and sets up its indices.
+    ///
+    /// The collection is obtained with `Database::collection_with_options()`, so if it already
+    /// exists, its options are left intact. Index creation is idempotent, so this method
+    /// can be safely called every time the application starts.
+    ///
+    /// # Failures
+    ///
+    /// Returns an error if the collection can't be created or if any of the indices can't
+    /// be set.
+    ///
+    /// # Example
+    ///
+    /// See `EjdbDocument` trait documentation.
+    pub fn ensure<T: EjdbDocument>(&self) -> Result<Collection> {
+        let coll =
+            try!(self.collection_with_options(T::collection_name(), T::collection_options()));
+        try!(T::ensure_indices(&coll));
+        Ok(coll)
+    }
+}
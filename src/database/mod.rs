@@ -1,5 +1,6 @@
 use std::borrow::Borrow;
-use std::ffi::{CStr, CString};
+use std::cell::Cell;
+use std::ffi::CString;
 use std::fmt;
 use std::io;
 use std::ptr;
@@ -13,14 +14,30 @@ use ejdb_sys;
 
 use self::open_mode::DatabaseOpenMode;
 use ejdb_bson::{EjdbBsonDocument, EjdbObjectId};
-use types::PartialSave;
+use raw::RawDocument;
+use types::{error_code_msg, PartialRemove, PartialSave};
+use utils::tclist::{TCList, TCValue};
 use utils::tcxstr::TCXString;
 use {Error, Result};
 
+#[cfg(feature = "async")]
+pub mod async_db;
+pub mod bulk;
+pub mod command;
+pub mod compiled;
+pub mod document;
 pub mod indices;
 pub mod meta;
+pub mod modify;
+pub mod paginate;
+pub mod parse;
+pub mod plan;
 pub mod query;
+pub mod shared;
+pub mod transfer;
 pub mod tx;
+pub mod typed;
+pub mod validate;
 
 /// Database open mode constants.
 ///
@@ -98,7 +115,7 @@ pub mod open_mode {
 ///
 /// This type has methods to access EJDB database metadata as well as methods for manipulating
 /// collections.
-pub struct Database(*mut ejdb_sys::EJDB);
+pub struct Database(*mut ejdb_sys::EJDB, Cell<u64>);
 
 // Database is not tied to a thread, so it is sendable.
 unsafe impl Send for Database {}
@@ -122,14 +139,6 @@ fn last_error_code(ejdb: *mut ejdb_sys::EJDB) -> i32 {
     unsafe { ejdb_sys::ejdbecode(ejdb) }
 }
 
-fn error_code_msg(code: i32) -> &'static str {
-    unsafe {
-        let msg = ejdb_sys::ejdberrmsg(code);
-        let msg_cstr = CStr::from_ptr(msg);
-        str::from_utf8_unchecked(msg_cstr.to_bytes())
-    }
-}
-
 impl Database {
     /// Opens the specified database with the provided open mode.
     ///
@@ -162,12 +171,12 @@ impl Database {
         let p = try!(CString::new(path).map_err(|_| "invalid path specified"));
 
         if unsafe { ejdb_sys::ejdbopen(ejdb, p.as_ptr(), open_mode.bits() as c_int) } {
-            Ok(Database(ejdb))
+            Ok(Database(ejdb, Cell::new(0)))
         } else {
-            Err(format!(
-                "cannot open database: {}",
-                error_code_msg(last_error_code(ejdb))
-            ).into())
+            Err(Error::from_code(
+                last_error_code(ejdb),
+                "cannot open database",
+            ))
         }
     }
 
@@ -187,6 +196,19 @@ impl Database {
         DatabaseOpenMode::default().open(path)
     }
 
+    // Collection structures are freed when collections are dropped, including drops done
+    // by imports and commands, so raw collection pointers kept across operations are only
+    // valid while the generation returned by this method stays the same.
+    #[inline]
+    fn collections_generation(&self) -> u64 {
+        self.1.get()
+    }
+
+    #[inline]
+    fn invalidate_collections(&self) {
+        self.1.set(self.1.get() + 1);
+    }
+
     fn last_error_msg(&self) -> Option<&'static str> {
         match last_error_code(self.0) {
             0 => None,
@@ -195,11 +217,10 @@ impl Database {
     }
 
     fn last_error<T>(&self, msg: &'static str) -> Result<T> {
-        Err(format!(
-            "{}: {}",
-            msg,
-            self.last_error_msg().unwrap_or("unknown error")
-        ).into())
+        match last_error_code(self.0) {
+            0 => Err(format!("{}: unknown error", msg).into()),
+            code => Err(Error::from_code(code, msg)),
+        }
     }
 
     /// Returns the given collection by its name, if it exists.
@@ -232,12 +253,13 @@ impl Database {
         if coll.is_null() {
             match self.last_error_msg() {
                 None => Ok(None),
-                Some(msg) => Err(msg.into()),
+                Some(_) => self.last_error("cannot get collection"),
             }
         } else {
             Ok(Some(Collection {
                 coll: coll,
                 db: self,
+                _copy: None,
             }))
         }
     }
@@ -246,7 +268,8 @@ impl Database {
     ///
     /// `name` argument may be of any type convertible to a byte vector, for example, strings
     /// or byte slices. `CollectionOptions` specify which options the collection will have
-    /// if it doesn't exist; if it does exist, this argument is ignored.
+    /// if it doesn't exist; if it does exist, this argument is ignored. Use
+    /// `Collection::set_options()` to change options of an existing collection.
     ///
     /// See also `CollectionOptions::get_or_create()` method for a possibly more convenient
     /// alternative.
@@ -270,12 +293,7 @@ impl Database {
         options: CollectionOptions,
     ) -> Result<Collection> {
         let p = try!(CString::new(name).map_err(|_| "invalid collection name"));
-        let mut ejcollopts = ejdb_sys::EJCOLLOPTS {
-            large: options.large,
-            compressed: options.compressed,
-            records: options.records,
-            cachedrecords: options.cached_records as c_int,
-        };
+        let mut ejcollopts = options.to_ejdb();
         let coll = unsafe { ejdb_sys::ejdbcreatecoll(self.0, p.as_ptr(), &mut ejcollopts) };
         if coll.is_null() {
             self.last_error("cannot create or open a collection")
@@ -283,6 +301,7 @@ impl Database {
             Ok(Collection {
                 coll: coll,
                 db: self,
+                _copy: None,
             })
         }
     }
@@ -325,18 +344,166 @@ impl Database {
     /// ```
     pub fn drop_collection<S: Into<Vec<u8>>>(&self, name: S, prune: bool) -> Result<()> {
         let p = try!(CString::new(name).map_err(|_| "invalid collection name"));
+        self.invalidate_collections();
         if unsafe { ejdb_sys::ejdbrmcoll(self.0, p.as_ptr(), prune) } {
             Ok(())
         } else {
             self.last_error("cannot remove a collection")
         }
     }
+
+    /// Returns the names of all collections in the database.
+    ///
+    /// Unlike `Database::get_metadata()`, this method does not load and parse the whole
+    /// metadata document, so it is a cheap way to list collections.
+    ///
+    /// # Failures
+    ///
+    /// Returns an error if the corresponding EJDB operation cannot be completed successfully.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use ejdb::Database;
+    /// let db = Database::open("/path/to/db").unwrap();
+    /// for name in db.collection_names().unwrap() {
+    ///     println!("{}", name);
+    /// }
+    /// ```
+    pub fn collection_names(&self) -> Result<Vec<String>> {
+        let list = unsafe { ejdb_sys::ejdbgetcolls(self.0) };
+        if list.is_null() {
+            return self.last_error("cannot get collections list");
+        }
+        let list = unsafe { TCList::from_ptr(list) };
+
+        // ejdbgetcolls() fills the list with shallow copies of collection structures,
+        // so they are only used to read collection names
+        let names = (0..list.len())
+            .filter_map(|i| list.get(i))
+            .map(|coll| unsafe { collection_name(coll.as_ptr() as *const _).to_owned() })
+            .collect();
+        Ok(names)
+    }
+
+    /// Returns an iterator of handles to all collections in the database.
+    ///
+    /// This is a convenient alternative to calling `Database::get_collection()` for each name
+    /// returned by `Database::collection_names()`; the handles are obtained from the same
+    /// list of collections, without looking them up by name.
+    ///
+    /// # Failures
+    ///
+    /// Returns an error if the corresponding EJDB operations cannot be completed successfully.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use ejdb::Database;
+    /// let db = Database::open("/path/to/db").unwrap();
+    /// for coll in db.collections().unwrap() {
+    ///     coll.sync().unwrap();
+    /// }
+    /// ```
+    pub fn collections(&self) -> Result<CollectionIter> {
+        let list = unsafe { ejdb_sys::ejdbgetcolls(self.0) };
+        if list.is_null() {
+            return self.last_error("cannot get collections list");
+        }
+        Ok(CollectionIter {
+            list: unsafe { TCList::from_ptr(list) },
+            db: self,
+        })
+    }
+
+    /// Synchronizes the whole database, including all of its collections, to disk.
+    ///
+    /// Data written to EJDB is not guaranteed to reach the disk until it is synchronized,
+    /// unless the database was opened with `DatabaseOpenMode::SYNC` flag. This method
+    /// can be used to force the synchronization explicitly, for example, at checkpoints.
+    ///
+    /// # Failures
+    ///
+    /// Returns an error if the corresponding EJDB operation cannot be completed successfully.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use ejdb::Database;
+    /// let db = Database::open("/path/to/db").unwrap();
+    /// // write some data
+    /// db.sync().unwrap();
+    /// ```
+    pub fn sync(&self) -> Result<()> {
+        if unsafe { ejdb_sys::ejdbsyncdb(self.0) } {
+            Ok(())
+        } else {
+            self.last_error("cannot synchronize database")
+        }
+    }
+
+    /// Synchronizes all of the provided collections to disk.
+    ///
+    /// This is a way to batch several `Collection::sync()` calls when only some collections
+    /// need to be synchronized; collections are processed in order.
+    ///
+    /// # Failures
+    ///
+    /// Returns an error if synchronization of any of the collections has failed. None of
+    /// the collections after the failed one will be synchronized.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use ejdb::Database;
+    /// let db = Database::open("/path/to/db").unwrap();
+    /// let coll_1 = db.collection("collection_1").unwrap();
+    /// let coll_2 = db.collection("collection_2").unwrap();
+    /// // write some data
+    /// db.sync_collections(&[coll_1, coll_2]).unwrap();
+    /// ```
+    pub fn sync_collections<'c, I>(&self, collections: I) -> Result<()>
+    where
+        I: IntoIterator<Item = &'c Collection<'c>>,
+    {
+        for coll in collections {
+            try!(coll.sync());
+        }
+        Ok(())
+    }
+}
+
+/// An iterator over collections of a database.
+///
+/// Objects of this structure are returned by `Database::collections()` method.
+pub struct CollectionIter<'db> {
+    list: TCList,
+    db: &'db Database,
+}
+
+impl<'db> Iterator for CollectionIter<'db> {
+    type Item = Collection<'db>;
+
+    fn next(&mut self) -> Option<Collection<'db>> {
+        // list entries are shallow copies of collection structures; they only point to
+        // the state shared with the original structure, so they can be used as handles
+        self.list.shift().map(|copy| Collection {
+            coll: copy.as_ptr() as *mut _,
+            db: self.db,
+            _copy: Some(copy),
+        })
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.list.len(), Some(self.list.len()))
+    }
 }
 
 /// Represents a set of options of an EJDB collection.
 ///
-/// Used when new collections are created. It is not possible to change options of a created
-/// collection.
+/// Used when new collections are created. Options of an existing collection can be changed
+/// with `Collection::set_options()` and read with `Collection::options()`.
 ///
 /// This is a builder object, so you can chain method calls to set various options. Finally,
 /// you can create a collection with these options with `get_or_create()` method.
@@ -384,6 +551,15 @@ impl CollectionOptions {
         self
     }
 
+    fn to_ejdb(&self) -> ejdb_sys::EJCOLLOPTS {
+        ejdb_sys::EJCOLLOPTS {
+            large: self.large,
+            compressed: self.compressed,
+            records: self.records,
+            cachedrecords: self.cached_records as c_int,
+        }
+    }
+
     /// Invokes `db.collection_with_options(name, options)` with this object as an argument.
     ///
     /// This is a convenience method which allows setting options and creating a collection
@@ -409,6 +585,37 @@ impl CollectionOptions {
     }
 }
 
+/// Options of an existing collection, as returned by `Collection::options()`.
+///
+/// EJDB does not store the expected number of records a collection was created with; it only
+/// uses this number to choose the number of hash buckets in the collection file. Therefore
+/// this structure contains the number of buckets instead, and the expected number of records
+/// must be provided again to turn it into a `CollectionOptions` object with `with_records()`.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct StoredCollectionOptions {
+    /// Whether the collection is able to hold more than 2GB of data.
+    pub large: bool,
+    /// Whether records in the collection are compressed with DEFLATE.
+    pub compressed: bool,
+    /// The number of hash buckets in the collection file.
+    pub buckets: u64,
+    /// Maximum number of records cached in memory.
+    pub cached_records: i32,
+}
+
+impl StoredCollectionOptions {
+    /// Converts these options to a `CollectionOptions` object with the given expected number
+    /// of records, e.g. to change some of them with `Collection::set_options()`.
+    pub fn with_records(self, records: i64) -> CollectionOptions {
+        CollectionOptions {
+            large: self.large,
+            compressed: self.compressed,
+            records: records,
+            cached_records: self.cached_records,
+        }
+    }
+}
+
 impl Default for CollectionOptions {
     fn default() -> CollectionOptions {
         CollectionOptions {
@@ -420,6 +627,26 @@ impl Default for CollectionOptions {
     }
 }
 
+/// Determines how a document is saved when a record with the same id already exists.
+///
+/// Used by `Collection::save_all_with_mode()` method.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum SaveMode {
+    /// The existing record is replaced with the new document entirely, as with
+    /// `Collection::save()`.
+    Replace,
+    /// Fields of the new document are merged into the existing record, as with
+    /// `Collection::save_merge()`.
+    Merge,
+}
+
+impl Default for SaveMode {
+    #[inline]
+    fn default() -> SaveMode {
+        SaveMode::Replace
+    }
+}
+
 /// A handle to an EJDB collection.
 ///
 /// This structure is connected via a lifetime to the corresponding database object,
@@ -440,6 +667,23 @@ impl Default for CollectionOptions {
 pub struct Collection<'db> {
     coll: *mut ejdb_sys::EJCOLL,
     db: &'db Database,
+    // owns the collection structure copy `coll` points to, if any
+    _copy: Option<TCValue>,
+}
+
+unsafe fn collection_name<'a>(coll: *const ejdb_sys::EJCOLL) -> &'a str {
+    #[repr(C)]
+    struct EjcollInternal {
+        cname: *const c_char,
+        cnamesz: c_int,
+    }
+
+    let coll_internal = coll as *const EjcollInternal;
+    let data = (*coll_internal).cname as *const u8;
+    let size = (*coll_internal).cnamesz as usize;
+    let bytes = slice::from_raw_parts(data, size);
+    // XXX: should be safe, but need to check
+    str::from_utf8_unchecked(bytes)
 }
 
 impl<'db> Collection<'db> {
@@ -454,26 +698,7 @@ impl<'db> Collection<'db> {
     /// assert_eq!("some_collection", coll.name());
     /// ```
     pub fn name(&self) -> &str {
-        fn get_coll_name(coll: *mut ejdb_sys::EJCOLL) -> (*const u8, usize) {
-            #[repr(C)]
-            struct EjcollInternal {
-                cname: *const c_char,
-                cnamesz: c_int,
-            }
-
-            let coll_internal = coll as *const _ as *const EjcollInternal;
-            unsafe {
-                (
-                    (*coll_internal).cname as *const u8,
-                    (*coll_internal).cnamesz as usize,
-                )
-            }
-        }
-
-        let (data, size) = get_coll_name(self.coll);
-        let bytes = unsafe { slice::from_raw_parts(data, size) };
-        // XXX: should be safe, but need to check
-        unsafe { str::from_utf8_unchecked(bytes) }
+        unsafe { collection_name(self.coll) }
     }
 
     /// Saves the given BSON document to this collection, assigning it a fresh id, if needed.
@@ -508,11 +733,56 @@ impl<'db> Collection<'db> {
     /// # }
     /// ```
     pub fn save<D: Borrow<bson::Document>>(&self, doc: D) -> Result<oid::ObjectId> {
-        let mut ejdb_doc = try!(EjdbBsonDocument::from_bson(doc.borrow()));
+        self.save_with_mode(doc.borrow(), SaveMode::Replace)
+    }
+
+    /// Saves the given BSON document to this collection, merging it into the existing record
+    /// with the same id, if any.
+    ///
+    /// This method works like `Collection::save()`, except when a document with the same `_id`
+    /// is already present in the collection: instead of replacing it, fields of the provided
+    /// document are set in the stored document, and fields which are absent from
+    /// the provided document are retained. This allows updating a document partially
+    /// without loading it first or writing an update query.
+    ///
+    /// # Failures
+    ///
+    /// Returns an error if the provided document can't be converted to the EJDB one or
+    /// if some error occurs which prevents the corresponding EJDB operation from successful
+    /// completion.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # #[macro_use] extern crate ejdb;
+    /// # use ejdb::Database;
+    /// # fn main() {
+    /// let db = Database::open("/path/to/db").unwrap();
+    /// let coll = db.collection("some_collection").unwrap();
+    /// let id = coll.save(bson! { "name" => "FooBar", "count" => 12345 }).unwrap();
+    /// coll.save_merge(bson! { "_id" => (id.clone()), "count" => 54321 }).unwrap();
+    ///
+    /// let doc = coll.load(&id).unwrap().unwrap();
+    /// assert_eq!(doc.get_str("name").unwrap(), "FooBar");
+    /// assert_eq!(doc.get_i32("count").unwrap(), 54321);
+    /// # }
+    /// ```
+    pub fn save_merge<D: Borrow<bson::Document>>(&self, doc: D) -> Result<oid::ObjectId> {
+        self.save_with_mode(doc.borrow(), SaveMode::Merge)
+    }
+
+    fn save_with_mode(&self, doc: &bson::Document, mode: SaveMode) -> Result<oid::ObjectId> {
+        let mut ejdb_doc = try!(EjdbBsonDocument::from_bson(doc));
         let mut out_id = EjdbObjectId::empty();
 
-        if unsafe { ejdb_sys::ejdbsavebson(self.coll, ejdb_doc.as_raw_mut(), out_id.as_raw_mut()) }
-        {
+        if unsafe {
+            ejdb_sys::ejdbsavebson2(
+                self.coll,
+                ejdb_doc.as_raw_mut(),
+                out_id.as_raw_mut(),
+                mode == SaveMode::Merge,
+            )
+        } {
             Ok(out_id.into())
         } else {
             self.db.last_error("error saving BSON document")
@@ -589,13 +859,47 @@ impl<'db> Collection<'db> {
     /// # }
     /// ```
     pub fn save_all<I>(&self, docs: I) -> Result<Vec<oid::ObjectId>>
+    where
+        I: IntoIterator,
+        I::Item: Borrow<bson::Document>,
+    {
+        self.save_all_with_mode(docs, SaveMode::Replace)
+    }
+
+    /// Saves all BSON documents in the provided iterable to this collection using the
+    /// specified save mode.
+    ///
+    /// This method works like `Collection::save_all()`, except that with `SaveMode::Merge`
+    /// documents are saved as if by `Collection::save_merge()`.
+    ///
+    /// # Failures
+    ///
+    /// Fails in the same cases as `Collection::save_all()`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # #[macro_use] extern crate ejdb;
+    /// # use ejdb::{Database, SaveMode};
+    /// # use ejdb::bson::oid::ObjectId;
+    /// # fn main() {
+    /// let db = Database::open("/path/to/db").unwrap();
+    /// let coll = db.collection("some_collection").unwrap();
+    /// # let (id1, id2) = (ObjectId::new().unwrap(), ObjectId::new().unwrap());
+    /// coll.save_all_with_mode(&[
+    ///     bson!{ "_id" => id1, "count" => 123 },
+    ///     bson!{ "_id" => id2, "items" => [4, 5, 6] }
+    /// ], SaveMode::Merge).unwrap();
+    /// # }
+    /// ```
+    pub fn save_all_with_mode<I>(&self, docs: I, mode: SaveMode) -> Result<Vec<oid::ObjectId>>
     where
         I: IntoIterator,
         I::Item: Borrow<bson::Document>,
     {
         let mut result = Vec::new();
         for doc in docs {
-            match self.save(doc.borrow()) {
+            match self.save_with_mode(doc.borrow(), mode) {
                 Ok(id) => result.push(id),
                 Err(e) => {
                     return Err(Error::PartialSave(PartialSave {
@@ -608,6 +912,196 @@ impl<'db> Collection<'db> {
         Ok(result)
     }
 
+    /// Removes a document with the given id from this collection.
+    ///
+    /// This is a convenient way to delete a single object by its identifier without resorting
+    /// to queries. Returns `true` if the document with the specified id was present in the
+    /// collection before the removal, `false` otherwise; removing a non-existing document
+    /// is not an error. The existence check and the removal are done inside a transaction
+    /// on the collection, unless one is already active, so other transactions cannot
+    /// interleave with them.
+    ///
+    /// # Failures
+    ///
+    /// Returns an error if the corresponding EJDB operation can't be completed successfully.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use ejdb::Database;
+    /// # use ejdb::bson::oid::ObjectId;
+    /// let db = Database::open("/path/to/db").unwrap();
+    /// let coll = db.collection("some_collection").unwrap();
+    /// let existed = coll.remove(&ObjectId::with_string("1234567890abcdef0987feab").unwrap()).unwrap();
+    /// // existed is true if the document has actually been removed
+    /// ```
+    pub fn remove(&self, id: &oid::ObjectId) -> Result<bool> {
+        // starting a transaction while another one is active on this thread would block
+        let tx = if try!(self.transaction_active()) {
+            None
+        } else {
+            Some(try!(self.begin_transaction()))
+        };
+
+        let mut ejdb_oid: EjdbObjectId = id.clone().into();
+
+        // ejdbrmbson() reports success for missing records, so check for presence first
+        let existing = unsafe { ejdb_sys::ejdbloadbson(self.coll, ejdb_oid.as_raw()) };
+        if existing.is_null() {
+            return match self.db.last_error_msg() {
+                None => Ok(false),
+                Some(_) => self.db.last_error("error removing BSON document"),
+            };
+        }
+        drop(unsafe { EjdbBsonDocument::from_ptr(existing) });
+
+        if !unsafe { ejdb_sys::ejdbrmbson(self.coll, ejdb_oid.as_raw_mut()) } {
+            return self.db.last_error("error removing BSON document");
+        }
+        if let Some(tx) = tx {
+            try!(tx.commit());
+        }
+        Ok(true)
+    }
+
+    /// Removes all documents with ids from the provided iterable from this collection.
+    ///
+    /// Every id from the provided iterable will be removed from this collection as if
+    /// they all have been passed one by one to `Collection::remove()`. Returns a vector
+    /// of flags, one for each id, which are `true` if the corresponding document existed.
+    ///
+    /// # Failures
+    ///
+    /// Returns an error if removal of any of the provided documents has failed. As ids
+    /// are processed one by one, none of the documents after the failed one will be removed.
+    /// The error will contain a vector of identifiers of documents which has been processed
+    /// successfully, along with their existence flags.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # #[macro_use] extern crate ejdb;
+    /// # use ejdb::Database;
+    /// # fn main() {
+    /// let db = Database::open("/path/to/db").unwrap();
+    /// let coll = db.collection("some_collection").unwrap();
+    /// let ids = coll.save_all(&[
+    ///     bson!{ "name" => "Foo", "count" => 123 },
+    ///     bson!{ "name" => "Bar", "items" => [4, 5, 6] }
+    /// ]).unwrap();
+    /// assert_eq!(coll.remove_all(&ids).unwrap(), vec![true, true]);
+    /// # }
+    /// ```
+    pub fn remove_all<I>(&self, ids: I) -> Result<Vec<bool>>
+    where
+        I: IntoIterator,
+        I::Item: Borrow<oid::ObjectId>,
+    {
+        let mut processed = Vec::new();
+        for id in ids {
+            let id = id.borrow();
+            match self.remove(id) {
+                Ok(existed) => processed.push((id.clone(), existed)),
+                Err(e) => {
+                    return Err(Error::PartialRemove(PartialRemove {
+                        cause: Box::new(e),
+                        successful_ids: processed,
+                    }))
+                }
+            }
+        }
+        Ok(processed.into_iter().map(|(_, existed)| existed).collect())
+    }
+
+    /// Synchronizes this collection to disk.
+    ///
+    /// Like `Database::sync()`, but only flushes the data and indices of this collection.
+    ///
+    /// # Failures
+    ///
+    /// Returns an error if the corresponding EJDB operation can't be completed successfully.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use ejdb::Database;
+    /// let db = Database::open("/path/to/db").unwrap();
+    /// let coll = db.collection("some_collection").unwrap();
+    /// // write some data
+    /// coll.sync().unwrap();
+    /// ```
+    pub fn sync(&self) -> Result<()> {
+        if unsafe { ejdb_sys::ejdbsyncoll(self.coll) } {
+            Ok(())
+        } else {
+            self.db.last_error("cannot synchronize collection")
+        }
+    }
+
+    /// Returns the current options of this collection.
+    ///
+    /// Options are read from the database metadata, so this method is as expensive as
+    /// `Database::get_metadata()`. EJDB does not store the expected number of records
+    /// the collection was created with, so it is not a part of the returned options;
+    /// see `StoredCollectionOptions` for more information.
+    ///
+    /// # Failures
+    ///
+    /// Returns an error if the database metadata can't be loaded or if it does not contain
+    /// this collection.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use ejdb::Database;
+    /// let db = Database::open("/path/to/db").unwrap();
+    /// let coll = db.collection("some_collection").unwrap();
+    /// let options = coll.options().unwrap();
+    /// if !options.compressed {
+    ///     coll.set_options(options.with_records(1_024_000).compressed(true)).unwrap();
+    /// }
+    /// ```
+    pub fn options(&self) -> Result<StoredCollectionOptions> {
+        let meta = try!(self.db.get_metadata());
+        let result = match meta.collections().find(|c| c.name() == self.name()) {
+            Some(coll) => Ok(StoredCollectionOptions {
+                large: coll.large(),
+                compressed: coll.compressed(),
+                buckets: coll.buckets(),
+                cached_records: coll.cached_records() as i32,
+            }),
+            None => Err("cannot find collection in database metadata".into()),
+        };
+        result
+    }
+
+    /// Changes options of this collection.
+    ///
+    /// Changing the `large` or `compressed` flags or the expected number of records makes
+    /// EJDB rebuild the collection file, which may take a lot of time for large collections.
+    ///
+    /// # Failures
+    ///
+    /// Returns an error if the corresponding EJDB operation can't be completed successfully.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use ejdb::{Database, CollectionOptions};
+    /// let db = Database::open("/path/to/db").unwrap();
+    /// let coll = db.collection("some_collection").unwrap();
+    /// coll.set_options(CollectionOptions::default().compressed(true).cached_records(1024))
+    ///     .unwrap();
+    /// ```
+    pub fn set_options(&self, options: CollectionOptions) -> Result<()> {
+        let mut ejcollopts = options.to_ejdb();
+        if unsafe { ejdb_sys::ejdbsetcolopts(self.coll, &mut ejcollopts) } {
+            Ok(())
+        } else {
+            self.db.last_error("cannot set collection options")
+        }
+    }
+
     /// Prepares the provided query for execution.
     ///
     /// This method accepts a query object and returns a prepared query object which can
@@ -641,7 +1135,9 @@ impl<'db> Collection<'db> {
             coll: self,
             query: query,
             hints: hints,
+            or_queries: Vec::new(),
             log_out: None,
+            validate: cfg!(debug_assertions),
         }
     }
 }
@@ -653,11 +1149,16 @@ impl<'db> Collection<'db> {
 /// query is executed on and therefore cannot outlive it.
 ///
 /// `PreparedQuery` is created using `Collection::query()` method.
+///
+/// In debug builds queries are checked for common problems before execution; in release builds
+/// this can be enabled with `validated()` method.
 pub struct PreparedQuery<'coll, 'db: 'coll, 'out, Q, H> {
     coll: &'coll Collection<'db>,
     query: Q,
     hints: H,
+    or_queries: Vec<query::Query>,
     log_out: Option<&'out mut io::Write>,
+    validate: bool,
 }
 
 impl<'coll, 'db, 'out, Q, H> PreparedQuery<'coll, 'db, 'out, Q, H>
@@ -699,10 +1200,68 @@ where
             coll: self.coll,
             query: self.query,
             hints: self.hints,
+            or_queries: self.or_queries,
             log_out: Some(target),
+            validate: self.validate,
         }
     }
 
+    /// Attaches an additional query to this one, joined with OR.
+    ///
+    /// Records matching any of the attached queries, or the main query itself, are selected.
+    /// Unlike `$or` operator inside a single query document (see `Query::or()`), each of the
+    /// queries attached with this method is planned by EJDB separately, so each of them can use
+    /// its own index. Query hints apply to the whole result.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use ejdb::Database;
+    /// use ejdb::query::{Q, QH};
+    ///
+    /// let db = Database::open("/path/to/db").unwrap();
+    /// let coll = db.collection("some_collection").unwrap();
+    /// let n = coll.query(Q.field("name").eq("Foo"), QH.empty())
+    ///     .or_query(Q.field("count").gt(10))
+    ///     .or_query(Q.field("tags").str_or(vec!["bar", "baz"]))
+    ///     .count().unwrap();
+    /// ```
+    pub fn or_query(mut self, query: query::Query) -> Self {
+        self.or_queries.push(query);
+        self
+    }
+
+    /// Enables validation of this query before it is executed.
+    ///
+    /// The query, the queries attached with `or_query()` and the hints are checked with
+    /// `Query::validate()` and `QueryHints::validate()`, so malformed queries are reported
+    /// with `Error::QueryValidation` listing the exact problems instead of a generic EJDB
+    /// error. Validation is always enabled in debug builds, so this method is only needed
+    /// to validate queries in release builds.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # #[macro_use] extern crate ejdb;
+    /// # use ejdb::Database;
+    /// use ejdb::query::{Query, QH};
+    /// use ejdb::Error;
+    ///
+    /// # fn main() {
+    /// let db = Database::open("/path/to/db").unwrap();
+    /// let coll = db.collection("some_collection").unwrap();
+    /// let q = Query::from(bson! { "count" => { "$bt" => [1] } });
+    /// match coll.query(q, QH.empty()).validated().count() {
+    ///     Err(Error::QueryValidation(e)) => println!("invalid query: {}", e),
+    ///     _ => unreachable!(),
+    /// }
+    /// # }
+    /// ```
+    pub fn validated(mut self) -> Self {
+        self.validate = true;
+        self
+    }
+
     /// Executes the query, returning the number of affected records.
     ///
     /// This method is equivalent to `find().map(|r| r.len())` but more efficient because
@@ -793,11 +1352,8 @@ where
     /// ```
     pub fn find_one(self) -> Result<Option<bson::Document>> {
         self.execute(ejdb_sys::JBQRYFINDONE)
-            .map(|(r, n)| QueryResult {
-                result: r,
-                current: 0,
-                total: n,
-            }).and_then(|qr| match qr.into_iter().next() {
+            .map(|(r, _)| QueryResult::new(r))
+            .and_then(|qr| match qr.into_iter().next() {
                 Some(r) => r.map(Some),
                 None => Ok(None),
             })
@@ -828,52 +1384,104 @@ where
     /// let items: Result<Vec<_>, _> = result.collect();  // collect all found records into a vector
     /// ```
     pub fn find(self) -> Result<QueryResult> {
-        self.execute(0).map(|(r, n)| QueryResult {
-            result: r,
-            current: 0,
-            total: n,
-        })
+        self.execute(0).map(|(r, _)| QueryResult::new(r))
     }
 
     fn execute(self, flags: u32) -> Result<(ejdb_sys::EJQRESULT, u32)> {
-        let query = self.query.borrow().as_bson();
-        let hints = self.hints.borrow().as_bson();
+        if self.validate {
+            try!(validate_query(
+                self.query.borrow(),
+                &self.or_queries,
+                self.hints.borrow()
+            ));
+        }
+
+        let query = try!(NativeQuery::new(
+            self.coll.db,
+            self.query.borrow().as_bson(),
+            self.hints.borrow().as_bson(),
+            &self.or_queries
+        ));
+        query.execute(self.coll, flags, self.log_out)
+    }
+}
 
+fn validate_query(
+    query: &query::Query,
+    or_queries: &[query::Query],
+    hints: &query::QueryHints,
+) -> Result<()> {
+    try!(query.validate());
+    for query in or_queries {
+        try!(query.validate());
+    }
+    hints.validate()
+}
+
+/// An owned native EJDB query object.
+struct NativeQuery(*mut ejdb_sys::EJQ);
+
+impl Drop for NativeQuery {
+    fn drop(&mut self) {
+        unsafe {
+            ejdb_sys::ejdbquerydel(self.0);
+        }
+    }
+}
+
+impl NativeQuery {
+    fn new(
+        db: &Database,
+        query: &bson::Document,
+        hints: &bson::Document,
+        or_queries: &[query::Query],
+    ) -> Result<NativeQuery> {
         let mut query_doc = Vec::new();
         try!(bson::encode_document(&mut query_doc, query));
 
-        let query =
-            unsafe { ejdb_sys::ejdbcreatequery2(self.coll.db.0, query_doc.as_ptr() as *const _) };
+        let query = unsafe { ejdb_sys::ejdbcreatequery2(db.0, query_doc.as_ptr() as *const _) };
         if query.is_null() {
-            return self.coll.db.last_error("error creating query object");
-        }
-
-        struct QueryGuard(*mut ejdb_sys::EJQ);
-        impl Drop for QueryGuard {
-            fn drop(&mut self) {
-                unsafe {
-                    ejdb_sys::ejdbquerydel(self.0);
-                }
-            }
+            return db.last_error("error creating query object");
         }
 
-        let mut query = QueryGuard(query);
+        let mut query = NativeQuery(query);
 
         if !hints.is_empty() {
             query_doc.clear();
             try!(bson::encode_document(&mut query_doc, hints));
 
-            let new_query = unsafe {
-                ejdb_sys::ejdbqueryhints(self.coll.db.0, query.0, query_doc.as_ptr() as *const _)
-            };
+            let new_query =
+                unsafe { ejdb_sys::ejdbqueryhints(db.0, query.0, query_doc.as_ptr() as *const _) };
             if new_query.is_null() {
-                return self.coll.db.last_error("error setting query hints");
+                return db.last_error("error setting query hints");
             }
 
             query.0 = new_query;
         }
 
-        let mut log = if self.log_out.is_some() {
+        // EJDB takes ownership of the queries created from these documents and frees them
+        // along with the main query
+        for or_query in or_queries {
+            query_doc.clear();
+            try!(bson::encode_document(&mut query_doc, or_query.as_bson()));
+
+            let new_query =
+                unsafe { ejdb_sys::ejdbqueryaddor(db.0, query.0, query_doc.as_ptr() as *const _) };
+            if new_query.is_null() {
+                return db.last_error("error adding $or query");
+            }
+        }
+
+        Ok(query)
+    }
+
+    fn execute(
+        &self,
+        coll: &Collection,
+        flags: u32,
+        log_out: Option<&mut io::Write>,
+    ) -> Result<(ejdb_sys::EJQRESULT, u32)> {
+        let mut log = if log_out.is_some() {
             Some(TCXString::new())
         } else {
             None
@@ -882,14 +1490,14 @@ where
 
         let mut count = 0;
         let result = unsafe {
-            ejdb_sys::ejdbqryexecute(self.coll.coll, query.0, &mut count, flags as c_int, log_ptr)
+            ejdb_sys::ejdbqryexecute(coll.coll, self.0, &mut count, flags as c_int, log_ptr)
         };
         if result.is_null() && (flags & ejdb_sys::JBQRYCOUNT) == 0 {
-            return self.coll.db.last_error("error executing query");
+            return coll.db.last_error("error executing query");
         }
 
         // dump the log to the output
-        match (log, self.log_out) {
+        match (log, log_out) {
             (Some(log), Some(log_out)) => {
                 try!(log_out.write(&log));
             }
@@ -903,20 +1511,149 @@ where
 /// An iterator over EJDB query results.
 ///
 /// Objects of this structure are returned by `PreparedQuery::find()` method.
+///
+/// EJDB loads all records matching a query at once, so a query result is, essentially,
+/// a list of BSON documents. Besides being a double-ended iterator, `QueryResult` provides
+/// random access to its records with `get()` method, and `iter()` method returns an iterator
+/// which does not consume the result. Documents are decoded only when they are accessed.
+///
+/// # Example
+///
+/// ```no_run
+/// # use ejdb::Database;
+/// use ejdb::query::{Q, QH};
+///
+/// let db = Database::open("/path/to/db").unwrap();
+/// let coll = db.collection("some_collection").unwrap();
+/// let result = coll.query(Q.field("name").eq("Foo"), QH.empty()).find().unwrap();
+///
+/// let last = result.get(result.total() - 1);
+/// for doc in result.iter().rev() {
+///     // records in reverse order
+/// }
+/// ```
 pub struct QueryResult {
     result: ejdb_sys::EJQRESULT,
-    current: c_int,
+    range: IndexRange,
     total: u32,
 }
 
 impl QueryResult {
+    fn new(result: ejdb_sys::EJQRESULT) -> QueryResult {
+        let total = if result.is_null() {
+            0
+        } else {
+            unsafe { ejdb_sys::ejdbqresultnum(result) as u32 }
+        };
+        QueryResult {
+            result: result,
+            range: IndexRange::new(total),
+            total: total,
+        }
+    }
+
     /// Returns the number of records returned by the query.
     ///
-    /// This iterator contains exactly `count()` elements.
+    /// This is the total number of records in the result, regardless of how many of them
+    /// have been consumed by iteration.
     #[inline]
     pub fn count(&self) -> u32 {
         self.total
     }
+
+    /// Returns the number of records returned by the query.
+    ///
+    /// Like `count()`, this method returns the total number of records in the result, while
+    /// `ExactSizeIterator::len()` returns the number of records not yet consumed by iteration.
+    #[inline]
+    pub fn total(&self) -> usize {
+        self.total as usize
+    }
+
+    /// Returns `true` if the query returned no records.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.total == 0
+    }
+
+    /// Decodes and returns the record with the given index, if it is in bounds.
+    ///
+    /// Indices are counted from the beginning of the result, regardless of how many records
+    /// have been consumed by iteration.
+    ///
+    /// # Failures
+    ///
+    /// Returns an error if the record can't be decoded into a BSON document.
+    pub fn get(&self, i: usize) -> Option<Result<bson::Document>> {
+        if i >= self.total() {
+            return None;
+        }
+        let mut data = self.raw_data(i as u32);
+        Some(bson::decode_document(&mut data).map_err(|e| e.into()))
+    }
+
+    /// Returns an iterator over all records of this result which does not consume it.
+    ///
+    /// The returned iterator always starts from the beginning of the result.
+    #[inline]
+    pub fn iter(&self) -> QueryResultIter {
+        QueryResultIter {
+            result: self,
+            range: IndexRange::new(self.total),
+        }
+    }
+
+    /// Returns a zero-copy view of the record with the given index, if it is in bounds.
+    ///
+    /// Unlike `get()`, this method does not decode the record; its fields are looked up
+    /// lazily, directly in the query result buffer. See `raw` module documentation for
+    /// more information.
+    ///
+    /// # Failures
+    ///
+    /// Returns an error if the record is not a valid BSON document.
+    pub fn get_raw(&self, i: usize) -> Option<Result<RawDocument>> {
+        if i >= self.total() {
+            return None;
+        }
+        Some(RawDocument::new(self.raw_data(i as u32)))
+    }
+
+    /// Returns an iterator of zero-copy views of all records of this result.
+    ///
+    /// Like `iter()`, this method does not consume the result and always starts from
+    /// its beginning.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use ejdb::Database;
+    /// use ejdb::query::{Q, QH};
+    ///
+    /// let db = Database::open("/path/to/db").unwrap();
+    /// let coll = db.collection("some_collection").unwrap();
+    /// let result = coll.query(Q.field("name").exists(true), QH.empty()).find().unwrap();
+    /// for doc in result.raw_iter() {
+    ///     let doc = doc.unwrap();
+    ///     println!("{}", doc.get_str("name").unwrap().unwrap_or("<not a string>"));
+    /// }
+    /// ```
+    #[inline]
+    pub fn raw_iter(&self) -> RawQueryResultIter {
+        RawQueryResultIter {
+            result: self,
+            range: IndexRange::new(self.total),
+        }
+    }
+
+    fn raw_data(&self, i: u32) -> &[u8] {
+        let mut item_size = 0;
+        unsafe {
+            let item = ejdb_sys::ejdbqresultbsondata(self.result, i as c_int, &mut item_size);
+            assert!(!item.is_null(), "query result item {} is not available", i);
+            slice::from_raw_parts(item as *const u8, item_size as usize)
+        }
+    }
 }
 
 impl Drop for QueryResult {
@@ -930,18 +1667,144 @@ impl Drop for QueryResult {
 impl Iterator for QueryResult {
     type Item = Result<bson::Document>;
 
+    #[inline]
     fn next(&mut self) -> Option<Result<bson::Document>> {
-        let mut item_size = 0;
-        let item: *const u8 = unsafe {
-            ejdb_sys::ejdbqresultbsondata(self.result, self.current, &mut item_size) as *const _
-        };
-        if item.is_null() {
+        self.range.next().and_then(|i| self.get(i))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.range.size_hint()
+    }
+}
+
+impl DoubleEndedIterator for QueryResult {
+    #[inline]
+    fn next_back(&mut self) -> Option<Result<bson::Document>> {
+        self.range.next_back().and_then(|i| self.get(i))
+    }
+}
+
+impl ExactSizeIterator for QueryResult {}
+
+impl<'r> IntoIterator for &'r QueryResult {
+    type Item = Result<bson::Document>;
+    type IntoIter = QueryResultIter<'r>;
+
+    #[inline]
+    fn into_iter(self) -> QueryResultIter<'r> {
+        self.iter()
+    }
+}
+
+/// A borrowing iterator over EJDB query results.
+///
+/// Objects of this structure are returned by `QueryResult::iter()` method.
+pub struct QueryResultIter<'r> {
+    result: &'r QueryResult,
+    range: IndexRange,
+}
+
+impl<'r> Iterator for QueryResultIter<'r> {
+    type Item = Result<bson::Document>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Result<bson::Document>> {
+        let result = self.result;
+        self.range.next().and_then(|i| result.get(i))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.range.size_hint()
+    }
+}
+
+impl<'r> DoubleEndedIterator for QueryResultIter<'r> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Result<bson::Document>> {
+        let result = self.result;
+        self.range.next_back().and_then(|i| result.get(i))
+    }
+}
+
+impl<'r> ExactSizeIterator for QueryResultIter<'r> {}
+
+/// A borrowing iterator of zero-copy views of EJDB query results.
+///
+/// Objects of this structure are returned by `QueryResult::raw_iter()` method.
+pub struct RawQueryResultIter<'r> {
+    result: &'r QueryResult,
+    range: IndexRange,
+}
+
+impl<'r> Iterator for RawQueryResultIter<'r> {
+    type Item = Result<RawDocument<'r>>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Result<RawDocument<'r>>> {
+        let result = self.result;
+        self.range.next().and_then(|i| result.get_raw(i))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.range.size_hint()
+    }
+}
+
+impl<'r> DoubleEndedIterator for RawQueryResultIter<'r> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Result<RawDocument<'r>>> {
+        let result = self.result;
+        self.range.next_back().and_then(|i| result.get_raw(i))
+    }
+}
+
+impl<'r> ExactSizeIterator for RawQueryResultIter<'r> {}
+
+/// A double-ended iterator over indices of query result records not consumed yet,
+/// shared by all query result iterators.
+struct IndexRange {
+    front: u32,
+    back: u32,
+}
+
+impl IndexRange {
+    #[inline]
+    fn new(total: u32) -> IndexRange {
+        IndexRange {
+            front: 0,
+            back: total,
+        }
+    }
+}
+
+impl Iterator for IndexRange {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.front == self.back {
             return None;
         }
-        self.current += 1;
+        self.front += 1;
+        Some(self.front as usize - 1)
+    }
 
-        let mut data = unsafe { slice::from_raw_parts(item, item_size as usize) };
-        Some(bson::decode_document(&mut data).map_err(|e| e.into()))
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let n = (self.back - self.front) as usize;
+        (n, Some(n))
+    }
+}
+
+impl DoubleEndedIterator for IndexRange {
+    fn next_back(&mut self) -> Option<usize> {
+        if self.front == self.back {
+            return None;
+        }
+        self.back -= 1;
+        Some(self.back as usize)
     }
 }
 
@@ -0,0 +1,422 @@
+//! Atomic read-modify operations over query results.
+//!
+//! EJDB does not report which records have been affected by update queries. The methods
+//! in this module find the matching records and modify them inside a collection transaction.
+//! EJDB serializes transactions only against each other: other transactions on the collection
+//! cannot interleave with these steps, but writers which do not use transactions can still
+//! change the records in between. Either all changes made by a method are applied, or none
+//! of them are.
+//!
+//! EJDB waits for an active transaction to finish before starting a new one, so starting
+//! a transaction while another one is active on the same thread never returns. These methods
+//! check for an active transaction and fail instead, therefore they cannot be called inside
+//! `Collection::transaction()` or while a `Transaction` guard is alive.
+
+use std::borrow::Borrow;
+use std::vec;
+
+use bson::{oid, Bson, Document};
+
+use super::parse::is_update_operator;
+use super::query;
+use super::tx::Transaction;
+use super::{validate_query, Collection, PreparedQuery};
+use Result;
+
+/// Determines which state of a document is returned by `PreparedQuery::find_and_modify()`.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ReturnDocument {
+    /// Return the document as it was before the update.
+    Before,
+    /// Return the document as it is after the update.
+    After,
+}
+
+impl<'coll, 'db, 'out, Q, H> PreparedQuery<'coll, 'db, 'out, Q, H>
+where
+    Q: Borrow<query::Query>,
+    H: Borrow<query::QueryHints>,
+{
+    /// Atomically updates the first record matching the query and returns it.
+    ///
+    /// The query should contain update operations, e.g. `$set` or `$inc`. The first record
+    /// matching the query, according to the hints, is found and then updated with these
+    /// operations; other matching records are not modified. Both steps are executed
+    /// inside a transaction on the collection. Depending on `ret`, the document is returned
+    /// in its state before or after the update; `$fields` hint is applied to it in
+    /// both cases.
+    ///
+    /// The found record is updated with the whole query restricted to its id, so positional
+    /// and element-matching updates work. If OR queries are attached with `or_query()`, only
+    /// the update operations are applied to the found record instead, since it may not match
+    /// the main query.
+    ///
+    /// If no record matches the query, nothing is modified and `None` is returned, unless
+    /// the query contains an `$upsert` operation or `upsert` is `true`. In the latter case,
+    /// `$set` operation of the query is turned into `$upsert`. A new record is then created
+    /// from the `$upsert` fields, and it is returned for `ReturnDocument::After`.
+    ///
+    /// # Failures
+    ///
+    /// Fails if a transaction is already active on the collection, if the transaction cannot
+    /// be started or committed, if any of the queries fail, if the found record does not match
+    /// the update query anymore because a non-transactional writer has changed it, or if
+    /// `upsert` is `true` but the query contains neither `$set` nor `$upsert` operation.
+    /// The transaction is aborted on errors.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use ejdb::Database;
+    /// use ejdb::modify::ReturnDocument;
+    /// use ejdb::query::{Q, QH};
+    ///
+    /// let db = Database::open("/path/to/db").unwrap();
+    /// let coll = db.collection("jobs").unwrap();
+    /// let job = coll
+    ///     .query(Q.field("state").eq("new").set("state", "taken"), QH.order_by("created").asc())
+    ///     .find_and_modify(ReturnDocument::After, false)
+    ///     .unwrap();
+    /// // `job` is the oldest new job, which is now taken
+    /// ```
+    pub fn find_and_modify(self, ret: ReturnDocument, upsert: bool) -> Result<Option<Document>> {
+        let PreparedQuery {
+            coll,
+            query,
+            hints,
+            or_queries,
+            log_out,
+            validate,
+        } = self;
+        if validate {
+            try!(validate_query(query.borrow(), &or_queries, hints.borrow()));
+        }
+        let query = query.borrow().as_bson();
+        let hints = hints.borrow();
+        let or_branches = !or_queries.is_empty();
+
+        let tx = try!(begin_transaction(coll));
+        let before = try!(PreparedQuery {
+            coll: coll,
+            query: selector(query),
+            hints: hints,
+            or_queries: or_queries,
+            log_out: log_out,
+            validate: false,
+        }
+        .find_one());
+
+        let id = match before {
+            Some(ref doc) => {
+                let id = try!(document_id(doc));
+                let update = record_update(query, &id, or_branches);
+                if try!(coll.query(update, query::QueryHints::new()).update()) != 1 {
+                    return Err("matched record has changed before it could be updated".into());
+                }
+                id
+            }
+            None => {
+                if !upsert && !query.contains_key("$upsert") {
+                    try!(tx.commit());
+                    return Ok(None);
+                }
+                let mut query = query.clone();
+                if upsert && !query.contains_key("$upsert") {
+                    match query.remove("$set") {
+                        Some(set) => query.insert("$upsert", set),
+                        None => return Err("upsert requires $set or $upsert operation".into()),
+                    };
+                }
+                let id = try!(assign_upsert_id(&mut query)).unwrap();
+                try!(coll
+                    .query(query::Query::from(query), query::QueryHints::new())
+                    .update());
+                id
+            }
+        };
+
+        let result = match ret {
+            ReturnDocument::Before => before,
+            ReturnDocument::After => {
+                let mut projection = query::QueryHints::new();
+                if let Some(fields) = hints.get("$fields") {
+                    projection.insert("$fields", fields.clone());
+                }
+                try!(coll
+                    .query(query::Query::new().id(id), projection)
+                    .find_one())
+            }
+        };
+        try!(tx.commit());
+        Ok(result)
+    }
+
+    /// Atomically removes all records matching the query and returns them.
+    ///
+    /// Matching records are found according to the hints, so e.g. `$max` and `$orderby`
+    /// hints can be used to remove only some of them, and then removed by their ids. Both
+    /// steps are executed inside a transaction on the collection. Update operations,
+    /// if there are any in the query, are ignored.
+    ///
+    /// # Failures
+    ///
+    /// Fails if a transaction is already active on the collection, if the transaction cannot
+    /// be started or committed, if the query or any of the removals fail, or if some of
+    /// the matching documents do not have an object id, e.g. because it is excluded by
+    /// the `$fields` hint. The transaction is aborted on errors, so no records are removed
+    /// in this case.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use ejdb::Database;
+    /// use ejdb::query::{Q, QH};
+    ///
+    /// let db = Database::open("/path/to/db").unwrap();
+    /// let coll = db.collection("some_collection").unwrap();
+    /// let archive = db.collection("archive").unwrap();
+    /// let removed = coll.query(Q.field("state").eq("done"), QH.empty())
+    ///     .find_and_remove()
+    ///     .unwrap();
+    /// archive.save_all(removed).unwrap();
+    /// ```
+    pub fn find_and_remove(self) -> Result<RemovedDocuments> {
+        let PreparedQuery {
+            coll,
+            query,
+            hints,
+            or_queries,
+            log_out,
+            validate,
+        } = self;
+        if validate {
+            try!(validate_query(query.borrow(), &or_queries, hints.borrow()));
+        }
+
+        let tx = try!(begin_transaction(coll));
+        let documents: Vec<Document> = try!(try!(PreparedQuery {
+            coll: coll,
+            query: selector(query.borrow().as_bson()),
+            hints: hints,
+            or_queries: or_queries,
+            log_out: log_out,
+            validate: false,
+        }
+        .find())
+        .collect());
+        for doc in &documents {
+            let id = try!(document_id(doc));
+            if !try!(coll.remove(&id)) {
+                return Err("matched document has disappeared during removal".into());
+            }
+        }
+        try!(tx.commit());
+        Ok(RemovedDocuments(documents.into_iter()))
+    }
+
+    /// Executes the update query and returns ids of all affected records.
+    ///
+    /// Works like `PreparedQuery::update()`, but instead of the number of affected records
+    /// it returns their ids, in the order the records are found according to the hints.
+    /// This includes records removed by `$dropall` and the record created by `$upsert`,
+    /// if no record matches the query. Like the count returned by `update()`, the ids cover
+    /// all matching records, even if the update operations leave some of them unchanged.
+    ///
+    /// The records are found and then updated one by one inside a transaction on
+    /// the collection. Each record is updated with the whole query restricted to its id,
+    /// so positional and element-matching updates behave as in `update()`; if OR queries
+    /// are attached with `or_query()`, only the update operations are applied instead.
+    /// Only ids of records which have actually been matched by these updates are returned,
+    /// so records changed by non-transactional writers after they have been found so that
+    /// they do not match anymore are left out.
+    ///
+    /// # Failures
+    ///
+    /// Fails if a transaction is already active on the collection, if the transaction cannot
+    /// be started or committed, if any of the queries fail, or if some of the matching records
+    /// do not have an object id. The transaction is aborted on errors, so no records are
+    /// modified in this case.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use ejdb::Database;
+    /// use ejdb::query::{Q, QH};
+    ///
+    /// let db = Database::open("/path/to/db").unwrap();
+    /// let coll = db.collection("some_collection").unwrap();
+    /// let ids = coll.query(Q.field("name").eq("Foo").inc("count", 1), QH.empty())
+    ///     .update_returning_ids()
+    ///     .unwrap();
+    /// println!("updated records: {:?}", ids);
+    /// ```
+    pub fn update_returning_ids(self) -> Result<Vec<oid::ObjectId>> {
+        let PreparedQuery {
+            coll,
+            query,
+            hints,
+            or_queries,
+            log_out,
+            validate,
+        } = self;
+        if validate {
+            try!(validate_query(query.borrow(), &or_queries, hints.borrow()));
+        }
+        let query = query.borrow().as_bson();
+        // only ids of the matching records are needed
+        let mut hints = hints.borrow().clone();
+        hints.remove("$fields");
+        let or_branches = !or_queries.is_empty();
+
+        let tx = try!(begin_transaction(coll));
+        let documents: Vec<Document> = try!(try!(PreparedQuery {
+            coll: coll,
+            query: selector(query),
+            hints: hints,
+            or_queries: or_queries,
+            log_out: log_out,
+            validate: false,
+        }
+        .find())
+        .collect());
+
+        let mut ids = Vec::with_capacity(documents.len());
+        for doc in &documents {
+            let id = try!(document_id(doc));
+            let update = record_update(query, &id, or_branches);
+            // the record is skipped if it has stopped matching since it has been found
+            if try!(coll.query(update, query::QueryHints::new()).update()) == 1 {
+                ids.push(id);
+            }
+        }
+        if documents.is_empty() && query.contains_key("$upsert") {
+            let mut query = query.clone();
+            let id = try!(assign_upsert_id(&mut query)).unwrap();
+            try!(coll
+                .query(query::Query::from(query), query::QueryHints::new())
+                .update());
+            ids.push(id);
+        }
+        try!(tx.commit());
+        Ok(ids)
+    }
+}
+
+/// An iterator over documents removed by `PreparedQuery::find_and_remove()`.
+///
+/// Unlike `QueryResult`, the documents are already decoded, so this iterator yields
+/// `bson::Document` values directly.
+pub struct RemovedDocuments(vec::IntoIter<Document>);
+
+impl Iterator for RemovedDocuments {
+    type Item = Document;
+
+    #[inline]
+    fn next(&mut self) -> Option<Document> {
+        self.0.next()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl DoubleEndedIterator for RemovedDocuments {
+    #[inline]
+    fn next_back(&mut self) -> Option<Document> {
+        self.0.next_back()
+    }
+}
+
+impl ExactSizeIterator for RemovedDocuments {}
+
+/// Starts a transaction, failing instead of waiting if one is already active on the collection.
+fn begin_transaction<'coll>(coll: &'coll Collection) -> Result<Transaction<'coll, 'coll>> {
+    if try!(coll.transaction_active()) {
+        return Err("a transaction is already active on the collection".into());
+    }
+    coll.begin_transaction()
+}
+
+/// Returns the query without its update operations.
+fn selector(query: &Document) -> query::Query {
+    let mut result = Document::new();
+    for (key, value) in query {
+        if !is_update_operator(key) {
+            result.insert(key.clone(), value.clone());
+        }
+    }
+    result.into()
+}
+
+/// Returns the query which applies the update operations of `query` to the found record
+/// with the given id.
+///
+/// The selector is kept for positional and element-matching updates, unless the record may
+/// have been matched by one of the OR queries, which the selector does not include; in that
+/// case only the update operations are applied.
+fn record_update(query: &Document, id: &oid::ObjectId, or_branches: bool) -> query::Query {
+    let mut update = Document::new();
+    for (key, value) in query {
+        if !or_branches || is_update_operator(key) {
+            update.insert(key.clone(), value.clone());
+        }
+    }
+    update.insert("_id", id.clone());
+    update.into()
+}
+
+/// Assigns a fresh id to the document inserted by `$upsert` operation of the query, unless
+/// it already has one, and returns this id, or `None` if the query has no `$upsert` operation.
+fn assign_upsert_id(query: &mut Document) -> Result<Option<oid::ObjectId>> {
+    match query.get_mut("$upsert") {
+        Some(&mut Bson::Document(ref mut doc)) => {
+            let id = match doc.get("_id") {
+                Some(&Bson::ObjectId(ref id)) => id.clone(),
+                Some(_) => return Err("$upsert document has an invalid _id field".into()),
+                None => try!(oid::ObjectId::new().map_err(|e| e.to_string())),
+            };
+            doc.insert("_id", id.clone());
+            Ok(Some(id))
+        }
+        Some(_) => Err("$upsert value must be a document".into()),
+        None => Ok(None),
+    }
+}
+
+fn document_id(doc: &Document) -> Result<oid::ObjectId> {
+    match doc.get("_id") {
+        Some(&Bson::ObjectId(ref id)) => Ok(id.clone()),
+        _ => Err("matched document has no object id".into()),
+    }
+}
+
+#[test]
+fn test_split_update_query() {
+    use super::query::Q;
+
+    let q = Q
+        .field("name")
+        .eq("Foo")
+        .set("count", 1)
+        .inc("total", 2)
+        .join("refs", "other")
+        .into_bson();
+    assert_eq!(
+        selector(&q).into_bson(),
+        bson! { "name" => "Foo", "$do" => { "refs" => { "$join" => "other" } } }
+    );
+
+    let mut q = Q.field("name").eq("Foo").upsert("count", 1).into_bson();
+    let id = assign_upsert_id(&mut q).unwrap().unwrap();
+    assert_eq!(
+        q.get_document("$upsert")
+            .unwrap()
+            .get_object_id("_id")
+            .unwrap(),
+        &id
+    );
+    assert_eq!(assign_upsert_id(&mut q).unwrap(), Some(id));
+    assert_eq!(assign_upsert_id(&mut bson! { "a" => 1 }).unwrap(), None);
+}
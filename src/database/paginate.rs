@@ -0,0 +1,424 @@
+//! Keyset pagination over ordered queries.
+//!
+//! Paging with `$skip` and `$max` hints makes EJDB scan and discard all records before
+//! the requested page, so each subsequent page is slower than the previous one. `Paginator`
+//! instead sorts the records by the pagination field and then by `_id`, remembers the sort key
+//! and the id of the last returned record, and restricts the query for the next page to
+//! the records following them: those with a greater (or smaller, for descending order) key,
+//! and those with an equal key and a greater (or smaller) id. Therefore pages neither skip
+//! nor repeat records, even when many records share the same key, and the cursor has
+//! a constant size.
+//!
+//! The position of a paginator is described by a `PageCursor`, which can be converted to
+//! an opaque string token, e.g. to be handed to an HTTP client, and used later to resume
+//! pagination with `Paginator::resume()`.
+
+use std::borrow::Borrow;
+use std::fmt;
+use std::io::Cursor;
+use std::result;
+use std::str::FromStr;
+
+use bson::{self, oid, Bson, Document};
+use serde::de::{self, Deserialize, Deserializer};
+use serde::{Serialize, Serializer};
+
+use super::query;
+use super::Collection;
+use {Error, Result};
+
+impl<'db> Collection<'db> {
+    /// Creates a paginator which returns the results of the query page by page.
+    ///
+    /// The hints must contain an `$orderby` hint for exactly one field, which is used as
+    /// the pagination key. Keys are compared by EJDB with `$gt` and `$lt` operators, so
+    /// all records should have values of the same type in this field. `$max` and `$skip`
+    /// hints are managed by the paginator and must not be present. Other hints are passed
+    /// to every page query as is.
+    ///
+    /// # Failures
+    ///
+    /// Returns an error if the hints do not satisfy the requirements above or if `page_size`
+    /// is zero.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use ejdb::Database;
+    /// use ejdb::query::{Q, QH};
+    ///
+    /// let db = Database::open("/path/to/db").unwrap();
+    /// let coll = db.collection("some_collection").unwrap();
+    ///
+    /// let mut pages = coll.paginate(Q.field("active").eq(true), QH.order_by("created").desc(), 20)
+    ///     .unwrap();
+    /// let page = pages.next_page().unwrap();
+    /// // hand `page.documents` and the token to a client
+    /// let token = page.next.map(|c| c.to_token());
+    /// ```
+    pub fn paginate<Q, H>(&self, query: Q, hints: H, page_size: u32) -> Result<Paginator>
+    where
+        Q: Borrow<query::Query>,
+        H: Borrow<query::QueryHints>,
+    {
+        let hints = hints.borrow();
+        if page_size == 0 {
+            return Err("page size must be positive".into());
+        }
+        if hints.contains_key("$max") || hints.contains_key("$skip") {
+            return Err("pagination hints must not contain $max or $skip".into());
+        }
+        let (field, descending) = match hints.get_document("$orderby") {
+            Ok(order) if order.len() == 1 => {
+                let (field, value) = order.iter().next().unwrap();
+                match *value {
+                    Bson::I32(1) | Bson::I64(1) => (field.clone(), false),
+                    Bson::I32(-1) | Bson::I64(-1) => (field.clone(), true),
+                    _ => return Err(format!("invalid $orderby value for field {}", field).into()),
+                }
+            }
+            _ => return Err("pagination hints must contain $orderby for exactly one field".into()),
+        };
+
+        Ok(Paginator {
+            coll: self,
+            query: query.borrow().clone(),
+            hints: hints.clone(),
+            field: field,
+            descending: descending,
+            page_size: page_size,
+            cursor: None,
+            exhausted: false,
+        })
+    }
+}
+
+/// A page of query results returned by `Paginator::next_page()`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Page {
+    /// Documents on this page.
+    pub documents: Vec<Document>,
+    /// The cursor pointing after this page, or `None` if this is the last page.
+    pub next: Option<PageCursor>,
+}
+
+/// A paginator over the results of an ordered query.
+///
+/// See the module documentation for more information. `Paginator` is created with
+/// `Collection::paginate()` method.
+pub struct Paginator<'coll, 'db: 'coll> {
+    coll: &'coll Collection<'db>,
+    query: query::Query,
+    hints: query::QueryHints,
+    field: String,
+    descending: bool,
+    page_size: u32,
+    cursor: Option<PageCursor>,
+    exhausted: bool,
+}
+
+impl<'coll, 'db> Paginator<'coll, 'db> {
+    /// Continues pagination from the position described by the cursor.
+    ///
+    /// # Failures
+    ///
+    /// Returns an error if the cursor was created by a paginator with a different
+    /// ordering.
+    pub fn resume(mut self, cursor: PageCursor) -> Result<Self> {
+        if cursor.field != self.field || cursor.descending != self.descending {
+            return Err("page cursor does not match the ordering of the paginator".into());
+        }
+        self.cursor = Some(cursor);
+        self.exhausted = false;
+        Ok(self)
+    }
+
+    /// Returns the cursor pointing after the last returned page, if any page has been
+    /// returned.
+    #[inline]
+    pub fn cursor(&self) -> Option<&PageCursor> {
+        self.cursor.as_ref()
+    }
+
+    /// Fetches the next page.
+    ///
+    /// When all records have been returned, an empty page is returned.
+    ///
+    /// # Failures
+    ///
+    /// Fails if the query fails or if the last record on the page has no object id, or its
+    /// pagination field is missing or is not a scalar value.
+    pub fn next_page(&mut self) -> Result<Page> {
+        if self.exhausted {
+            return Ok(Page {
+                documents: Vec::new(),
+                next: None,
+            });
+        }
+
+        let mut query = self.query.clone();
+        if let Some(ref cursor) = self.cursor {
+            let condition = following(&self.field, &cursor.key, &cursor.id, self.descending);
+            add_condition(&mut query, condition);
+        }
+        let direction = if self.descending { -1 } else { 1 };
+        let mut order = Document::new();
+        order.insert(self.field.clone(), direction);
+        if self.field != "_id" {
+            order.insert("_id", direction);
+        }
+        let mut hints = self.hints.clone();
+        hints.insert("$orderby", order);
+        let hints = hints.max(self.page_size as i64 + 1);
+
+        let mut documents: Vec<Document> =
+            try!(try!(self.coll.query(&query, &hints).find()).collect());
+        let has_more = documents.len() > self.page_size as usize;
+        documents.truncate(self.page_size as usize);
+
+        let cursor = match documents.last() {
+            Some(last) => {
+                let key = match get_path(last, &self.field) {
+                    Some(key) if is_scalar(key) => key.clone(),
+                    _ => {
+                        return Err(format!(
+                            "pagination field {} is missing or is not a scalar value",
+                            self.field
+                        )
+                        .into())
+                    }
+                };
+                let id = match last.get("_id") {
+                    Some(&Bson::ObjectId(ref id)) => id.clone(),
+                    _ => return Err("paginated document has no object id".into()),
+                };
+                Some(PageCursor {
+                    field: self.field.clone(),
+                    descending: self.descending,
+                    key: key,
+                    id: id,
+                })
+            }
+            None => self.cursor.take(),
+        };
+
+        self.cursor = cursor;
+        self.exhausted = !has_more;
+        Ok(Page {
+            documents: documents,
+            next: if has_more { self.cursor.clone() } else { None },
+        })
+    }
+}
+
+/// Returns a condition selecting the records which follow the record with the given key and id.
+fn following(field: &str, key: &Bson, id: &oid::ObjectId, descending: bool) -> Document {
+    let op = if descending { "$lt" } else { "$gt" };
+    if field == "_id" {
+        return bson! { "_id" => { op => (id.clone()) } };
+    }
+    bson! {
+        "$or" => [
+            { field => { op => (key.clone()) } },
+            { field => (key.clone()), "_id" => { op => (id.clone()) } }
+        ]
+    }
+}
+
+/// Adds a condition to the query, keeping its own `$or` and `$and` constraints intact.
+fn add_condition(query: &mut query::Query, condition: Document) {
+    if let Some(&mut Bson::Array(ref mut conditions)) = query.get_mut("$and") {
+        conditions.push(condition.into());
+        return;
+    }
+    query.insert("$and", vec![Bson::from(condition)]);
+}
+
+fn get_path<'d>(doc: &'d Document, path: &str) -> Option<&'d Bson> {
+    let mut current = doc;
+    let mut parts = path.split('.').peekable();
+    while let Some(part) = parts.next() {
+        match (current.get(part), parts.peek().is_some()) {
+            (Some(&Bson::Document(ref doc)), true) => current = doc,
+            (value, false) => return value,
+            (_, true) => return None,
+        }
+    }
+    None
+}
+
+fn is_scalar(value: &Bson) -> bool {
+    match *value {
+        Bson::Null | Bson::Document(_) | Bson::Array(_) => false,
+        _ => true,
+    }
+}
+
+/// Maximum length of a cursor token accepted by `PageCursor::from_token()`.
+///
+/// Tokens may come from untrusted sources, so longer ones are rejected before decoding.
+pub const MAX_TOKEN_LENGTH: usize = 8192;
+
+/// A position of a `Paginator`.
+///
+/// A cursor can be converted to an opaque string token with `to_token()` method or `Display`
+/// implementation, and restored from it with `from_token()` or `FromStr` implementation.
+/// It is also serialized as this token with serde.
+#[derive(Clone, PartialEq, Debug)]
+pub struct PageCursor {
+    field: String,
+    descending: bool,
+    key: Bson,
+    id: oid::ObjectId,
+}
+
+impl PageCursor {
+    /// Converts this cursor to an opaque string token.
+    ///
+    /// The token only contains ASCII alphanumeric characters. Tokens of cursors with long
+    /// string keys may exceed `MAX_TOKEN_LENGTH`, and then they can't be restored.
+    pub fn to_token(&self) -> String {
+        let doc = bson! {
+            "f" => (self.field.clone()),
+            "d" => (self.descending),
+            "k" => (self.key.clone()),
+            "i" => (self.id.clone())
+        };
+        let mut data = Vec::new();
+        bson::encode_document(&mut data, &doc).expect("cursor document is always encodable");
+
+        static CHARS: &'static [u8] = b"0123456789abcdef";
+        let mut token = String::with_capacity(data.len() * 2);
+        for byte in data {
+            token.push(CHARS[(byte >> 4) as usize] as char);
+            token.push(CHARS[(byte & 0xf) as usize] as char);
+        }
+        token
+    }
+
+    /// Restores a cursor from a token returned by `to_token()`.
+    ///
+    /// # Failures
+    ///
+    /// Returns an error if the token is malformed, if it is longer than `MAX_TOKEN_LENGTH`
+    /// or if it does not describe a cursor.
+    pub fn from_token(token: &str) -> Result<PageCursor> {
+        let invalid = || Error::from("invalid page cursor token");
+
+        let token = token.as_bytes();
+        if token.len() > MAX_TOKEN_LENGTH || token.len() % 2 != 0 {
+            return Err(invalid());
+        }
+        let mut data = Vec::with_capacity(token.len() / 2);
+        for pair in token.chunks(2) {
+            let digits = (
+                (pair[0] as char).to_digit(16),
+                (pair[1] as char).to_digit(16),
+            );
+            match digits {
+                (Some(hi), Some(lo)) => data.push((hi * 16 + lo) as u8),
+                _ => return Err(invalid()),
+            }
+        }
+
+        let mut doc = try!(bson::decode_document(&mut Cursor::new(data)).map_err(|_| invalid()));
+        if doc.len() != 4 {
+            return Err(invalid());
+        }
+        let field = try!(doc.get_str("f").map_err(|_| invalid())).to_owned();
+        let descending = try!(doc.get_bool("d").map_err(|_| invalid()));
+        let id = try!(doc.get_object_id("i").map_err(|_| invalid())).clone();
+        let key = match doc.remove("k") {
+            Some(key) => {
+                if is_scalar(&key) {
+                    key
+                } else {
+                    return Err(invalid());
+                }
+            }
+            None => return Err(invalid()),
+        };
+
+        Ok(PageCursor {
+            field: field,
+            descending: descending,
+            key: key,
+            id: id,
+        })
+    }
+}
+
+impl fmt::Display for PageCursor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.to_token())
+    }
+}
+
+impl FromStr for PageCursor {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<PageCursor> {
+        PageCursor::from_token(s)
+    }
+}
+
+impl Serialize for PageCursor {
+    fn serialize<S: Serializer>(&self, serializer: S) -> result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_token())
+    }
+}
+
+impl<'de> Deserialize<'de> for PageCursor {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> result::Result<Self, D::Error> {
+        let token = try!(String::deserialize(deserializer));
+        PageCursor::from_token(&token).map_err(de::Error::custom)
+    }
+}
+
+#[test]
+fn test_page_cursor_token() {
+    let cursor = PageCursor {
+        field: "a.b".into(),
+        descending: true,
+        key: Bson::FloatingPoint(12.5),
+        id: oid::ObjectId::with_bytes([1; 12]),
+    };
+    let token = cursor.to_token();
+    assert!(token.chars().all(|c| c.is_ascii_alphanumeric()));
+    assert_eq!(PageCursor::from_token(&token).unwrap(), cursor);
+    assert_eq!(token.parse::<PageCursor>().unwrap(), cursor);
+
+    assert!(PageCursor::from_token("").is_err());
+    assert!(PageCursor::from_token("abc").is_err());
+    assert!(PageCursor::from_token(&token[..token.len() - 2]).is_err());
+    assert!(PageCursor::from_token(&"0".repeat(MAX_TOKEN_LENGTH + 2)).is_err());
+
+    let encode = |doc: Document| {
+        let mut data = Vec::new();
+        bson::encode_document(&mut data, &doc).unwrap();
+        data.iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()
+    };
+    let id = oid::ObjectId::with_bytes([1; 12]);
+    let valid = bson! { "f" => "a", "d" => false, "k" => 1, "i" => (id.clone()) };
+    assert!(PageCursor::from_token(&encode(valid.clone())).is_ok());
+    let mut extra = valid.clone();
+    extra.insert("x", 1);
+    assert!(PageCursor::from_token(&encode(extra)).is_err());
+    let mut wrong_type = valid.clone();
+    wrong_type.insert("d", 0);
+    assert!(PageCursor::from_token(&encode(wrong_type)).is_err());
+    let mut not_scalar = valid.clone();
+    not_scalar.insert("k", bson! { "a" => 1 });
+    assert!(PageCursor::from_token(&encode(not_scalar)).is_err());
+    let mut missing = valid;
+    missing.remove("i");
+    assert!(PageCursor::from_token(&encode(missing)).is_err());
+
+    let cursor = PageCursor {
+        key: Bson::String("Foo".into()),
+        ..cursor
+    };
+    assert_eq!(PageCursor::from_token(&cursor.to_token()).unwrap(), cursor);
+}
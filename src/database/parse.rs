@@ -0,0 +1,846 @@
+//! Parsing of textual queries and query hints.
+//!
+//! The parser accepts relaxed JSON, as typed in the MongoDB shell: keys may be unquoted,
+//! strings may be enclosed in single quotes, trailing commas are allowed and `//` and `/* */`
+//! comments are skipped. Extended JSON values `{"$oid": ...}`, `{"$date": ...}`,
+//! `{"$regex": ..., "$options": ...}` and `{"$numberLong": ...}` are converted to
+//! the corresponding BSON values.
+
+use std::error;
+use std::fmt;
+use std::i32;
+
+use bson::{oid, Bson, Document};
+use chrono::{TimeZone, Utc};
+
+use super::query::{Query, QueryHints, PARAM_KEY};
+use Result;
+
+/// Operators which may appear at the top level of a query.
+pub(crate) const QUERY_OPERATORS: &'static [&'static str] = &["$and", "$or"];
+
+/// Update operators which may appear at the top level of a query.
+pub(crate) const UPDATE_OPERATORS: &'static [&'static str] = &[
+    "$set",
+    "$upsert",
+    "$inc",
+    "$dropall",
+    "$addToSet",
+    "$unset",
+    "$pull",
+    "$pullAll",
+    "$push",
+    "$pushAll",
+    "$rename",
+    "$do",
+];
+
+/// Checks whether the key is an operator which modifies records.
+///
+/// `$do` is listed among update operators, but its actions do not modify records.
+pub(crate) fn is_update_operator(key: &str) -> bool {
+    key != "$do" && UPDATE_OPERATORS.contains(&key)
+}
+
+/// Operators which may appear in a field constraint.
+pub(crate) const FIELD_OPERATORS: &'static [&'static str] = &[
+    "$begin",
+    "$bt",
+    "$gt",
+    "$gte",
+    "$lt",
+    "$lte",
+    "$exists",
+    "$elemMatch",
+    "$in",
+    "$nin",
+    "$icase",
+    "$not",
+    "$strand",
+    "$stror",
+];
+
+/// Actions which may appear in a `$do` block.
+pub(crate) const ACTION_OPERATORS: &'static [&'static str] = &["$join", "$slice"];
+
+/// Keys which may appear in query hints.
+pub(crate) const HINT_OPERATORS: &'static [&'static str] =
+    &["$max", "$skip", "$orderby", "$fields"];
+
+const EXTENDED_KEYS: &'static [&'static str] = &[
+    "$oid",
+    "$date",
+    "$regex",
+    "$options",
+    "$numberLong",
+    PARAM_KEY,
+];
+
+impl Query {
+    /// Parses a query from a string.
+    ///
+    /// The string should contain a query document in relaxed JSON, i.e. with optionally
+    /// unquoted keys, single-quoted strings, trailing commas and comments. Extended JSON
+    /// values (`$oid`, `$date`, `$regex` and `$numberLong`) are converted to their BSON
+    /// counterparts. `$date` accepts milliseconds since the Unix epoch or a date in
+    /// ISO 8601 format.
+    ///
+    /// All operators in the query are checked against those supported by the query builder
+    /// methods.
+    ///
+    /// # Failures
+    ///
+    /// Returns `Error::QueryParse` if the string is not a valid query; the error contains
+    /// the line and the column of the offending token.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ejdb::query::{Query, Q};
+    ///
+    /// let q = Query::parse("{ name: { $begin: 'Fo' }, count: { $gt: 3 }, }").unwrap();
+    /// assert_eq!(q, Q.field("name").begin("Fo").field("count").gt(3));
+    ///
+    /// assert!(Query::parse("{ name: { $beginz: 'Fo' } }").is_err());
+    /// ```
+    pub fn parse(s: &str) -> Result<Query> {
+        Parser::new(s).parse(Context::Query).map(Query::from)
+    }
+}
+
+impl QueryHints {
+    /// Parses query hints from a string.
+    ///
+    /// The string syntax is the same as for `Query::parse()`. Only `$max`, `$skip`, `$orderby`
+    /// and `$fields` keys are allowed at the top level.
+    ///
+    /// # Failures
+    ///
+    /// Returns `Error::QueryParse` if the string is not a valid hints document; the error
+    /// contains the line and the column of the offending token.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ejdb::query::{QueryHints, QH};
+    ///
+    /// let qh = QueryHints::parse("{ $orderby: { name: 1, age: -1 } }").unwrap();
+    /// assert_eq!(qh, QH.order_by("name").asc().order_by("age").desc());
+    /// ```
+    pub fn parse(s: &str) -> Result<QueryHints> {
+        Parser::new(s).parse(Context::Hints).map(QueryHints::from)
+    }
+}
+
+/// Parses an arbitrary document in relaxed JSON, without checking its keys.
+pub(crate) fn parse_document(s: &str) -> Result<Document> {
+    Parser::new(s).parse(Context::Any)
+}
+
+/// An error in a textual query or query hints.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct ParseError {
+    /// The line of the offending token, starting from 1.
+    pub line: usize,
+    /// The column of the offending token, starting from 1.
+    pub column: usize,
+    /// The description of the error.
+    pub message: String,
+}
+
+impl error::Error for ParseError {
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at line {}, column {}",
+            self.message, self.line, self.column
+        )
+    }
+}
+
+/// Determines which keys are allowed in a document and how its values are parsed.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum Context {
+    /// A query document, containing field constraints and query or update operators.
+    Query,
+    /// A list of queries, i.e. `$and` or `$or` value.
+    QueryList,
+    /// A field constraint.
+    Field,
+    /// A `$do` block, containing fields mapped to actions.
+    Actions,
+    /// An action in a `$do` block.
+    Action,
+    /// Query hints.
+    Hints,
+    /// Arbitrary values.
+    Any,
+}
+
+#[derive(Copy, Clone)]
+struct Position {
+    line: usize,
+    column: usize,
+}
+
+type ParseResult<T> = ::std::result::Result<T, ParseError>;
+
+/// Maximum nesting level of documents and arrays accepted by the parser.
+///
+/// Values are parsed recursively, so the limit prevents stack overflows on malicious input.
+const MAX_DEPTH: usize = 512;
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+    column: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn new(s: &str) -> Parser {
+        Parser {
+            chars: s.chars().collect(),
+            pos: 0,
+            line: 1,
+            column: 1,
+            depth: 0,
+        }
+    }
+
+    fn parse(mut self, context: Context) -> Result<Document> {
+        let doc = try!(self.parse_document(context));
+        try!(self.skip_whitespace());
+        match self.peek() {
+            None => Ok(doc),
+            Some(c) => Err(self.error(format!("unexpected character '{}'", c)).into()),
+        }
+    }
+
+    fn parse_document(&mut self, context: Context) -> ParseResult<Document> {
+        try!(self.skip_whitespace());
+        let start = self.position();
+        match try!(self.parse_value(context)) {
+            Bson::Document(doc) => Ok(doc),
+            _ => Err(error_at(start, "expected a document")),
+        }
+    }
+
+    fn position(&self) -> Position {
+        Position {
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    fn error<S: Into<String>>(&self, message: S) -> ParseError {
+        error_at(self.position(), message)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).cloned()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek();
+        if let Some(c) = c {
+            self.pos += 1;
+            if c == '\n' {
+                self.line += 1;
+                self.column = 1;
+            } else {
+                self.column += 1;
+            }
+        }
+        c
+    }
+
+    fn expect(&mut self, expected: char) -> ParseResult<()> {
+        try!(self.skip_whitespace());
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.bump();
+                Ok(())
+            }
+            Some(c) => Err(self.error(format!("expected '{}', found '{}'", expected, c))),
+            None => Err(self.error(format!("expected '{}', found end of input", expected))),
+        }
+    }
+
+    fn skip_whitespace(&mut self) -> ParseResult<()> {
+        loop {
+            match (self.peek(), self.peek_at(1)) {
+                (Some(c), _) if c.is_whitespace() => {
+                    self.bump();
+                }
+                (Some('/'), Some('/')) => {
+                    while let Some(c) = self.bump() {
+                        if c == '\n' {
+                            break;
+                        }
+                    }
+                }
+                (Some('/'), Some('*')) => {
+                    let start = self.position();
+                    self.bump();
+                    self.bump();
+                    loop {
+                        match (self.peek(), self.peek_at(1)) {
+                            (Some('*'), Some('/')) => {
+                                self.bump();
+                                self.bump();
+                                break;
+                            }
+                            (Some(_), _) => {
+                                self.bump();
+                            }
+                            (None, _) => return Err(error_at(start, "unterminated comment")),
+                        }
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn parse_value(&mut self, context: Context) -> ParseResult<Bson> {
+        try!(self.skip_whitespace());
+        match self.peek() {
+            Some(c) if c == '{' || c == '[' => {
+                if self.depth >= MAX_DEPTH {
+                    return Err(self.error(format!("nesting is deeper than {} levels", MAX_DEPTH)));
+                }
+                self.depth += 1;
+                let value = if c == '{' {
+                    self.parse_object(context)
+                } else {
+                    self.parse_array(context)
+                };
+                self.depth -= 1;
+                value
+            }
+            Some(q) if q == '"' || q == '\'' => self.parse_string().map(Bson::String),
+            Some(c) if c == '-' || c.is_digit(10) => self.parse_number(),
+            Some(c) if is_identifier_char(c) => {
+                let start = self.position();
+                match &*self.parse_identifier() {
+                    "true" => Ok(Bson::Boolean(true)),
+                    "false" => Ok(Bson::Boolean(false)),
+                    "null" => Ok(Bson::Null),
+                    other => Err(error_at(
+                        start,
+                        format!("unexpected identifier '{}'", other),
+                    )),
+                }
+            }
+            Some(c) => Err(self.error(format!("unexpected character '{}'", c))),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn parse_object(&mut self, context: Context) -> ParseResult<Bson> {
+        let start = self.position();
+        try!(self.expect('{'));
+        let mut doc = Document::new();
+        let mut extended = false;
+        loop {
+            try!(self.skip_whitespace());
+            if self.peek() == Some('}') {
+                self.bump();
+                break;
+            }
+
+            let key_start = self.position();
+            let key = try!(self.parse_key());
+            try!(self.expect(':'));
+            let value_context = if EXTENDED_KEYS.contains(&&*key) {
+                extended = true;
+                Context::Any
+            } else {
+                try!(key_context(context, &key, key_start))
+            };
+            let value = try!(self.parse_value(value_context));
+            if doc.contains_key(&key) {
+                return Err(error_at(key_start, format!("duplicate key '{}'", key)));
+            }
+            doc.insert(key, value);
+
+            try!(self.skip_whitespace());
+            match self.bump() {
+                Some(',') => {}
+                Some('}') => break,
+                Some(c) => {
+                    return Err(error_at(
+                        self.previous_position(),
+                        format!("expected ',' or '}}', found '{}'", c),
+                    ))
+                }
+                None => return Err(self.error("expected ',' or '}', found end of input")),
+            }
+        }
+
+        if extended {
+            convert_extended(doc, start)
+        } else {
+            Ok(Bson::Document(doc))
+        }
+    }
+
+    fn parse_array(&mut self, context: Context) -> ParseResult<Bson> {
+        let element_context = match context {
+            Context::QueryList => Context::Query,
+            _ => Context::Any,
+        };
+        try!(self.expect('['));
+        let mut arr = Vec::new();
+        loop {
+            try!(self.skip_whitespace());
+            if self.peek() == Some(']') {
+                self.bump();
+                break;
+            }
+
+            arr.push(try!(self.parse_value(element_context)));
+
+            try!(self.skip_whitespace());
+            match self.bump() {
+                Some(',') => {}
+                Some(']') => break,
+                Some(c) => {
+                    return Err(error_at(
+                        self.previous_position(),
+                        format!("expected ',' or ']', found '{}'", c),
+                    ))
+                }
+                None => return Err(self.error("expected ',' or ']', found end of input")),
+            }
+        }
+        Ok(Bson::Array(arr))
+    }
+
+    fn previous_position(&self) -> Position {
+        Position {
+            line: self.line,
+            column: self.column - 1,
+        }
+    }
+
+    fn parse_key(&mut self) -> ParseResult<String> {
+        match self.peek() {
+            Some(q) if q == '"' || q == '\'' => self.parse_string(),
+            Some(c) if is_identifier_char(c) => Ok(self.parse_identifier()),
+            Some(c) => Err(self.error(format!("expected a key, found '{}'", c))),
+            None => Err(self.error("expected a key, found end of input")),
+        }
+    }
+
+    fn parse_identifier(&mut self) -> String {
+        let mut result = String::new();
+        while let Some(c) = self.peek() {
+            if !is_identifier_char(c) {
+                break;
+            }
+            result.push(c);
+            self.bump();
+        }
+        result
+    }
+
+    fn parse_string(&mut self) -> ParseResult<String> {
+        let start = self.position();
+        let quote = self.bump().unwrap();
+        let mut result = String::new();
+        loop {
+            match self.bump() {
+                Some(c) if c == quote => return Ok(result),
+                Some('\\') => {
+                    let escape_start = self.previous_position();
+                    let c = match self.bump() {
+                        Some('"') => '"',
+                        Some('\'') => '\'',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => try!(self.parse_unicode_escape(escape_start)),
+                        _ => return Err(error_at(escape_start, "invalid escape sequence")),
+                    };
+                    result.push(c);
+                }
+                Some('\n') | None => return Err(error_at(start, "unterminated string")),
+                Some(c) => result.push(c),
+            }
+        }
+    }
+
+    fn parse_unicode_escape(&mut self, start: Position) -> ParseResult<char> {
+        let high = try!(self.parse_hex4(start));
+        let code = if high >= 0xd800 && high < 0xdc00 {
+            if self.bump() != Some('\\') || self.bump() != Some('u') {
+                return Err(error_at(start, "unpaired surrogate in unicode escape"));
+            }
+            let low = try!(self.parse_hex4(start));
+            if low < 0xdc00 || low >= 0xe000 {
+                return Err(error_at(start, "unpaired surrogate in unicode escape"));
+            }
+            0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+        } else {
+            high
+        };
+        ::std::char::from_u32(code).ok_or_else(|| error_at(start, "invalid unicode escape"))
+    }
+
+    fn parse_hex4(&mut self, start: Position) -> ParseResult<u32> {
+        let mut result = 0;
+        for _ in 0..4 {
+            match self.bump().and_then(|c| c.to_digit(16)) {
+                Some(d) => result = result * 16 + d,
+                None => return Err(error_at(start, "invalid unicode escape")),
+            }
+        }
+        Ok(result)
+    }
+
+    fn parse_number(&mut self) -> ParseResult<Bson> {
+        let start = self.position();
+        let mut text = String::new();
+        let mut is_float = false;
+        if self.peek() == Some('-') {
+            text.push('-');
+            self.bump();
+        }
+        while let Some(c) = self.peek() {
+            match c {
+                c if c.is_digit(10) => {}
+                '.' | 'e' | 'E' => is_float = true,
+                '+' | '-' if text.ends_with('e') || text.ends_with('E') => {}
+                _ => break,
+            }
+            text.push(c);
+            self.bump();
+        }
+
+        if is_float {
+            match text.parse::<f64>() {
+                Ok(n) => Ok(Bson::FloatingPoint(n)),
+                Err(_) => Err(error_at(start, format!("invalid number '{}'", text))),
+            }
+        } else {
+            match text.parse::<i64>() {
+                Ok(n) if n >= i32::MIN as i64 && n <= i32::MAX as i64 => Ok(Bson::I32(n as i32)),
+                Ok(n) => Ok(Bson::I64(n)),
+                Err(_) => Err(error_at(start, format!("invalid number '{}'", text))),
+            }
+        }
+    }
+}
+
+fn error_at<S: Into<String>>(position: Position, message: S) -> ParseError {
+    ParseError {
+        line: position.line,
+        column: position.column,
+        message: message.into(),
+    }
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '$' || c == '.'
+}
+
+fn key_context(context: Context, key: &str, position: Position) -> ParseResult<Context> {
+    fn check(allowed: &[&str], key: &str, what: &str, position: Position) -> ParseResult<()> {
+        if allowed.contains(&key) {
+            Ok(())
+        } else {
+            Err(error_at(position, format!("unknown {} '{}'", what, key)))
+        }
+    }
+
+    match context {
+        Context::Query | Context::QueryList if key.starts_with('$') => {
+            if QUERY_OPERATORS.contains(&key) {
+                Ok(Context::QueryList)
+            } else if key == "$do" {
+                Ok(Context::Actions)
+            } else {
+                try!(check(UPDATE_OPERATORS, key, "query operator", position));
+                Ok(Context::Any)
+            }
+        }
+        Context::Query | Context::QueryList => Ok(Context::Field),
+        Context::Field if key.starts_with('$') => {
+            try!(check(FIELD_OPERATORS, key, "field operator", position));
+            match key {
+                "$elemMatch" => Ok(Context::Query),
+                "$not" => Ok(Context::Field),
+                _ => Ok(Context::Any),
+            }
+        }
+        Context::Actions => Ok(Context::Action),
+        Context::Action => check(ACTION_OPERATORS, key, "action", position).map(|_| Context::Any),
+        Context::Hints => check(HINT_OPERATORS, key, "query hint", position).map(|_| Context::Any),
+        Context::Field | Context::Any => Ok(Context::Any),
+    }
+}
+
+fn convert_extended(doc: Document, position: Position) -> ParseResult<Bson> {
+    let invalid = |what: &str| error_at(position, format!("invalid {} value", what));
+
+    let keys: Vec<String> = doc.keys().cloned().collect();
+    let keys: Vec<&str> = keys.iter().map(|k| &**k).collect();
+    match &keys[..] {
+        ["$oid"] => match doc.get_str("$oid") {
+            Ok(hex) => oid::ObjectId::with_string(hex)
+                .map(Bson::ObjectId)
+                .map_err(|_| invalid("$oid")),
+            Err(_) => Err(invalid("$oid")),
+        },
+        ["$date"] => {
+            let millis = match doc.get("$date") {
+                Some(&Bson::I32(n)) => Some(n as i64),
+                Some(&Bson::I64(n)) => Some(n),
+                Some(&Bson::String(ref s)) => parse_iso_date(s),
+                Some(&Bson::Document(ref d)) if d.len() == 1 => {
+                    d.get_str("$numberLong").ok().and_then(|s| s.parse().ok())
+                }
+                _ => None,
+            };
+            millis
+                .and_then(utc_datetime)
+                .ok_or_else(|| invalid("$date"))
+        }
+        ["$regex"] | ["$regex", "$options"] | ["$options", "$regex"] => {
+            let options = if keys.len() == 2 {
+                try!(doc.get_str("$options").map_err(|_| invalid("$options")))
+            } else {
+                ""
+            };
+            doc.get_str("$regex")
+                .map(|pattern| Bson::RegExp(pattern.into(), options.into()))
+                .map_err(|_| invalid("$regex"))
+        }
+        ["$numberLong"] => doc
+            .get_str("$numberLong")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .map(Bson::I64)
+            .ok_or_else(|| invalid("$numberLong")),
+        [k] if *k == PARAM_KEY => match doc.get(PARAM_KEY) {
+            Some(&Bson::String(_)) => Ok(Bson::Document(doc)),
+            _ => Err(invalid(PARAM_KEY)),
+        },
+        _ => Err(error_at(position, "invalid Extended JSON document")),
+    }
+}
+
+/// Creates a UTC datetime BSON value from milliseconds since the Unix epoch.
+///
+/// Returns `None` if the value is out of the range supported by `chrono`.
+fn utc_datetime(millis: i64) -> Option<Bson> {
+    Utc.timestamp_millis_opt(millis).single().map(Bson::UtcDatetime)
+}
+
+/// Parses a date in `YYYY-MM-DD[THH:MM[:SS[.fff]]][Z|+HH:MM|-HH:MM]` format into
+/// milliseconds since the Unix epoch.
+fn parse_iso_date(s: &str) -> Option<i64> {
+    let s = s.as_bytes();
+    let number = |start: usize, len: usize| -> Option<i64> {
+        match s.get(start..start + len) {
+            Some(part) if part.iter().all(u8::is_ascii_digit) => {
+                Some(part.iter().fold(0, |n, &b| n * 10 + (b - b'0') as i64))
+            }
+            _ => None,
+        }
+    };
+    let separator = |i: usize, c: u8| s.get(i) == Some(&c);
+
+    let (year, month, day) = match (number(0, 4), number(5, 2), number(8, 2)) {
+        (Some(y), Some(m), Some(d)) if separator(4, b'-') && separator(7, b'-') => (y, m, d),
+        _ => return None,
+    };
+    if month < 1 || month > 12 || day < 1 || day > 31 {
+        return None;
+    }
+
+    let mut i = 10;
+    let mut millis = 0;
+    if separator(i, b'T') || separator(i, b' ') {
+        match (number(i + 1, 2), number(i + 4, 2)) {
+            (Some(h), Some(m)) if separator(i + 3, b':') && h < 24 && m < 60 => {
+                millis += (h * 60 + m) * 60_000
+            }
+            _ => return None,
+        }
+        i += 6;
+        if separator(i, b':') {
+            match number(i + 1, 2) {
+                Some(sec) if sec <= 60 => millis += sec * 1000,
+                _ => return None,
+            }
+            i += 3;
+            if separator(i, b'.') {
+                let digits = s[i + 1..].iter().take_while(|b| b.is_ascii_digit()).count();
+                if digits == 0 {
+                    return None;
+                }
+                let len = digits.min(3);
+                millis += number(i + 1, len).unwrap() * [100, 10, 1][len - 1];
+                i += 1 + digits;
+            }
+        }
+    }
+
+    match &s[i..] {
+        b"" | b"Z" => {}
+        rest if rest.len() == 6 && (rest[0] == b'+' || rest[0] == b'-') => {
+            let offset = match (number(i + 1, 2), number(i + 4, 2)) {
+                (Some(h), Some(m)) if separator(i + 3, b':') => (h * 60 + m) * 60_000,
+                _ => return None,
+            };
+            millis -= if rest[0] == b'+' { offset } else { -offset };
+        }
+        _ => return None,
+    }
+
+    Some(days_from_civil(year, month, day) * 86_400_000 + millis)
+}
+
+/// Returns the number of days since 1970-01-01 for the given date in the proleptic
+/// Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let month = (month + 9) % 12;
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[test]
+fn test_parse_query() {
+    use super::query::Q;
+
+    let q = Query::parse(
+        r#"
+        // find some records
+        {
+            name: { $begin: 'Fo' },
+            "count": { "$gt": 3, },
+            tags: { $in: ["a", 'b\'c'] },  /* trailing comma */
+        }
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        q,
+        Q.field("name")
+            .begin("Fo")
+            .field("count")
+            .gt(3)
+            .field("tags")
+            .contained_in(vec!["a", "b'c"])
+    );
+
+    let q = Query::parse(
+        "{ $or: [{ n: { $lt: 2 } }, { n: { $not: { $gte: 7.5 } } }], \
+         $set: { 'a.b': null, big: 3000000000 } }",
+    )
+    .unwrap();
+    assert_eq!(
+        q,
+        Q.or(vec![Q.field("n").lt(2), Q.field("n").not().gte(7.5),])
+            .set("a.b", Bson::Null)
+            .set("big", 3000000000i64)
+    );
+
+    let q = Query::parse(
+        "{ _id: { $oid: '507f1f77bcf86cd799439011' }, \
+         name: { $regex: '^fo+', $options: 'i' }, \
+         n: { $numberLong: '42' } }",
+    )
+    .unwrap();
+    assert_eq!(
+        q.get("_id"),
+        Some(&Bson::ObjectId(
+            oid::ObjectId::with_string("507f1f77bcf86cd799439011").unwrap()
+        ))
+    );
+    assert_eq!(
+        q.get("name"),
+        Some(&Bson::RegExp("^fo+".into(), "i".into()))
+    );
+    assert_eq!(q.get("n"), Some(&Bson::I64(42)));
+
+    assert_eq!(
+        Query::parse("{ d: { $date: '2001-02-03T04:05:06.789+01:00' } }").unwrap(),
+        Query::parse("{ d: { $date: 981169506789 } }").unwrap()
+    );
+    assert_eq!(
+        Query::parse("{ d: { $date: -1500 } }").unwrap().get("d"),
+        Some(&Bson::UtcDatetime(Utc.timestamp_opt(-2, 500_000_000).unwrap()))
+    );
+}
+
+#[test]
+fn test_parse_query_errors() {
+    fn error(s: &str) -> ParseError {
+        match Parser::new(s).parse(Context::Query) {
+            Err(::Error::QueryParse(e)) => e,
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    assert_eq!(
+        error("{\n  name: { $beginz: 'Fo' } }"),
+        ParseError {
+            line: 2,
+            column: 11,
+            message: "unknown field operator '$beginz'".into(),
+        }
+    );
+    assert_eq!(
+        error("{ $foo: 1 }").message,
+        "unknown query operator '$foo'"
+    );
+    assert_eq!(
+        error("{ a: 1 b: 2 }").to_string(),
+        "expected ',' or '}', found 'b' at line 1, column 8"
+    );
+    assert_eq!(error("{ a: 'abc }").message, "unterminated string");
+    assert_eq!(error("{ a: tru }").message, "unexpected identifier 'tru'");
+    assert_eq!(
+        error("{ a: { $oid: 'xyz' } }").message,
+        "invalid $oid value"
+    );
+    assert_eq!(error("[1, 2]").message, "expected a document");
+    assert_eq!(error("{ a: 1 } x").message, "unexpected character 'x'");
+    assert_eq!(error("{ a: 1, a: 2 }").message, "duplicate key 'a'");
+    assert_eq!(
+        error(&format!("{{ a: {}", "[".repeat(100_000))),
+        ParseError {
+            line: 1,
+            column: 517,
+            message: "nesting is deeper than 512 levels".into(),
+        }
+    );
+    let nested = format!("{{ a: {}1{} }}", "[".repeat(511), "]".repeat(511));
+    assert!(Query::parse(&nested).is_ok());
+
+    assert!(QueryHints::parse("{ $max: 1, $skip: 2 }").is_ok());
+    assert!(QueryHints::parse("{ $limit: 1 }").is_err());
+}
@@ -0,0 +1,342 @@
+//! Structured query execution plans.
+//!
+//! EJDB can write a free-text log describing how a query has been executed. This module
+//! provides `QueryPlan` structure which is parsed from this log. Query plans are usually
+//! obtained with `PreparedQuery::explain()` method, but they can also be parsed from logs
+//! collected with `PreparedQuery::log_output()` with `QueryPlan::parse()`.
+
+use std::borrow::Borrow;
+use std::fmt;
+use std::u32;
+
+use ejdb_sys;
+
+use super::meta::IndexType;
+use super::parse::is_update_operator;
+use super::{query, PreparedQuery, QueryResult};
+use Result;
+
+impl<'coll, 'db, 'out, Q, H> PreparedQuery<'coll, 'db, 'out, Q, H>
+where
+    Q: Borrow<query::Query>,
+    H: Borrow<query::QueryHints>,
+{
+    /// Executes the query, returning its execution plan instead of its results.
+    ///
+    /// The query is executed as if by `count()`, so no records are loaded or sorted; the plan
+    /// therefore has `count_only` flag set, and its `final_sorting` and `records_returned`
+    /// fields do not describe an execution with `find()`. Queries with update operations
+    /// are rejected, because executing them would modify the records.
+    /// If a logging target has been set with `log_output()`, the raw log is written to it
+    /// as well.
+    ///
+    /// # Failures
+    ///
+    /// Fails if the query or any of the `$or` queries added with `or_query()` contain update
+    /// operations, and in the same cases as `PreparedQuery::count()`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use ejdb::Database;
+    /// use ejdb::query::{Q, QH};
+    ///
+    /// let db = Database::open("/path/to/db").unwrap();
+    /// let coll = db.collection("some_collection").unwrap();
+    /// coll.index("name").string(true).set().unwrap();
+    ///
+    /// let plan = coll.query(Q.field("name").eq("Foo"), QH.empty()).explain().unwrap();
+    /// assert!(!plan.full_scan);
+    /// assert_eq!(plan.index.unwrap().field, "name");
+    /// println!("{}", plan);
+    /// ```
+    pub fn explain(self) -> Result<QueryPlan> {
+        let mut log = Vec::new();
+        let PreparedQuery {
+            coll,
+            query,
+            hints,
+            or_queries,
+            log_out,
+            validate,
+        } = self;
+        let main_query: &query::Query = query.borrow();
+        let updating = Some(main_query)
+            .into_iter()
+            .chain(&or_queries)
+            .any(|q| q.keys().any(|k| is_update_operator(k)));
+        if updating {
+            return Err("cannot explain a query with update operations".into());
+        }
+        {
+            let prepared = PreparedQuery {
+                coll: coll,
+                query: query,
+                hints: hints,
+                or_queries: or_queries,
+                log_out: Some(&mut log),
+                validate: validate,
+            };
+            let (result, _) = try!(prepared.execute(ejdb_sys::JBQRYCOUNT));
+            if !result.is_null() {
+                drop(QueryResult::new(result));
+            }
+        }
+        if let Some(log_out) = log_out {
+            try!(log_out.write_all(&log));
+        }
+        Ok(QueryPlan::parse(&String::from_utf8_lossy(&log)))
+    }
+}
+
+/// Describes an index used by a query.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct PlanIndex {
+    /// Index name, as it appears in the database metadata, e.g. `sname`.
+    pub name: String,
+    /// The path of the indexed field.
+    pub field: String,
+    /// Index type.
+    pub index_type: IndexType,
+    /// Whether a string index is case sensitive; always `true` for other index types.
+    pub case_sensitive: bool,
+}
+
+impl PlanIndex {
+    fn from_name(name: &str) -> Option<PlanIndex> {
+        let (index_type, case_sensitive) = match name.chars().next() {
+            Some('s') => (IndexType::Lexical, true),
+            Some('i') => (IndexType::Lexical, false),
+            Some('n') => (IndexType::Decimal, true),
+            Some('a') => (IndexType::Token, true),
+            _ => return None,
+        };
+        Some(PlanIndex {
+            name: name.into(),
+            field: name[1..].into(),
+            index_type: index_type,
+            case_sensitive: case_sensitive,
+        })
+    }
+}
+
+/// An execution plan of an EJDB query, parsed from the query log.
+///
+/// All fields have default values when the corresponding information is missing from the log.
+///
+/// EJDB does not log the number of records it has examined, so the plan does not contain it.
+/// `full_scan` flag and the number of matched records are the closest information available.
+///
+/// `Display` implementation of this structure provides a human-readable summary of the plan;
+/// the raw log is available in `log` field.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct QueryPlan {
+    /// The index used to select records, or `None` if no index was used.
+    pub index: Option<PlanIndex>,
+    /// Whether all records of the collection were examined.
+    pub full_scan: bool,
+    /// Whether the query contained update operations.
+    pub updating: bool,
+    /// Whether the query was executed in count-only mode.
+    pub count_only: bool,
+    /// `$max` hint value, or `None` if the number of records was not limited.
+    pub max: Option<u32>,
+    /// `$skip` hint value.
+    pub skip: u32,
+    /// The number of `$orderby` fields.
+    pub order_fields: u32,
+    /// Whether the results were sorted after fetching, i.e. sorting was not done by an index.
+    pub final_sorting: bool,
+    /// The number of active query conditions.
+    pub active_conditions: u32,
+    /// The number of top-level `$or` subqueries.
+    pub or_queries: u32,
+    /// The number of top-level `$and` subqueries.
+    pub and_queries: u32,
+    /// The number of records matching the query, before `$skip` and `$max` are applied.
+    pub records_matched: Option<u32>,
+    /// The number of records in the query result.
+    pub records_returned: Option<u32>,
+    /// Warnings reported by EJDB.
+    pub warnings: Vec<String>,
+    /// Log lines which were not recognized.
+    pub other: Vec<String>,
+    /// The raw query log.
+    pub log: String,
+}
+
+impl QueryPlan {
+    /// Parses an EJDB query log.
+    ///
+    /// Parsing is lenient: unknown lines are collected into `other` field, and invalid
+    /// values are ignored.
+    pub fn parse(log: &str) -> QueryPlan {
+        let mut plan = QueryPlan {
+            log: log.into(),
+            ..QueryPlan::default()
+        };
+        for line in log.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            if line == "RUN FULLSCAN" {
+                plan.full_scan = true;
+                continue;
+            }
+            if line.starts_with("WARNING") {
+                plan.warnings.push(line.into());
+                continue;
+            }
+            let (key, value) = match line.find(':') {
+                Some(i) => (&line[..i], line[i + 1..].trim()),
+                None => {
+                    plan.other.push(line.into());
+                    continue;
+                }
+            };
+            let yes = value == "YES";
+            let number = value.parse::<u32>().ok();
+            match key {
+                "UPDATING MODE" => plan.updating = yes,
+                "COUNT ONLY" => plan.count_only = yes,
+                "MAX" => plan.max = number.filter(|&n| n != u32::MAX),
+                "SKIP" => plan.skip = number.unwrap_or(0),
+                "MAIN IDX" => {
+                    let name = value.trim_matches('\'');
+                    plan.index = if name == "NONE" {
+                        None
+                    } else {
+                        PlanIndex::from_name(name)
+                    };
+                }
+                "ORDER FIELDS" => plan.order_fields = number.unwrap_or(0),
+                "FINAL SORTING" => plan.final_sorting = yes,
+                "ACTIVE CONDITIONS" => plan.active_conditions = number.unwrap_or(0),
+                "ROOT $OR QUERIES" => plan.or_queries = number.unwrap_or(0),
+                "ROOT $AND QUERIES" => plan.and_queries = number.unwrap_or(0),
+                "RS COUNT" => plan.records_matched = number,
+                "RS SIZE" => plan.records_returned = number,
+                _ => plan.other.push(line.into()),
+            }
+        }
+        plan
+    }
+}
+
+impl fmt::Display for QueryPlan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.index {
+            Some(ref index) => try!(writeln!(
+                f,
+                "index: {} ({:?}{}) on field {}",
+                index.name,
+                index.index_type,
+                if index.case_sensitive {
+                    ""
+                } else {
+                    ", case insensitive"
+                },
+                index.field
+            )),
+            None => try!(writeln!(f, "index: none")),
+        }
+        try!(writeln!(
+            f,
+            "full scan: {}",
+            if self.full_scan { "yes" } else { "no" }
+        ));
+        try!(writeln!(
+            f,
+            "conditions: {} ($or: {}, $and: {})",
+            self.active_conditions, self.or_queries, self.and_queries
+        ));
+        match self.max {
+            Some(max) => try!(writeln!(f, "skip: {}, max: {}", self.skip, max)),
+            None => try!(writeln!(f, "skip: {}, max: unlimited", self.skip)),
+        }
+        try!(writeln!(
+            f,
+            "order by: {} field(s), {}",
+            self.order_fields,
+            if self.final_sorting {
+                "sorted after fetching"
+            } else {
+                "no sorting needed"
+            }
+        ));
+        if self.updating || self.count_only {
+            try!(writeln!(
+                f,
+                "mode: {}{}",
+                if self.updating { "update" } else { "find" },
+                if self.count_only { ", count only" } else { "" }
+            ));
+        }
+        try!(write!(f, "records: "));
+        match self.records_matched {
+            Some(n) => try!(write!(f, "{} matched", n)),
+            None => try!(write!(f, "unknown matched")),
+        }
+        match self.records_returned {
+            Some(n) => try!(write!(f, ", {} returned", n)),
+            None => try!(write!(f, ", unknown returned")),
+        }
+        for warning in &self.warnings {
+            try!(write!(f, "\n{}", warning));
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_parse_query_plan() {
+    let log = "UPDATING MODE: NO\n\
+               MAX: 4294967295\n\
+               SKIP: 2\n\
+               COUNT ONLY: NO\n\
+               MAIN IDX: 'iaddress.city'\n\
+               MAIN IDX TCOP: 0\n\
+               ORDER FIELDS: 1\n\
+               ACTIVE CONDITIONS: 1\n\
+               ROOT $OR QUERIES: 0\n\
+               ROOT $AND QUERIES: 0\n\
+               FETCH ALL: NO\n\
+               RS COUNT: 10\n\
+               RS SIZE: 8\n\
+               FINAL SORTING: YES\n";
+    let plan = QueryPlan::parse(log);
+    assert_eq!(
+        plan.index,
+        Some(PlanIndex {
+            name: "iaddress.city".into(),
+            field: "address.city".into(),
+            index_type: IndexType::Lexical,
+            case_sensitive: false,
+        })
+    );
+    assert!(!plan.full_scan);
+    assert!(!plan.updating);
+    assert_eq!(plan.max, None);
+    assert_eq!(plan.skip, 2);
+    assert_eq!(plan.order_fields, 1);
+    assert!(plan.final_sorting);
+    assert_eq!(plan.active_conditions, 1);
+    assert_eq!(plan.records_matched, Some(10));
+    assert_eq!(plan.records_returned, Some(8));
+    assert_eq!(
+        plan.other,
+        vec!["MAIN IDX TCOP: 0".to_owned(), "FETCH ALL: NO".to_owned()]
+    );
+    assert_eq!(plan.log, log);
+
+    let plan = QueryPlan::parse("MAX: 10\nMAIN IDX: 'NONE'\nRUN FULLSCAN\n");
+    assert_eq!(plan.index, None);
+    assert!(plan.full_scan);
+    assert_eq!(plan.max, Some(10));
+    assert_eq!(
+        plan.to_string(),
+        "index: none\n\
+         full scan: yes\n\
+         conditions: 0 ($or: 0, $and: 0)\n\
+         skip: 0, max: 10\n\
+         order by: 0 field(s), no sorting needed\n\
+         records: unknown matched, unknown returned"
+    );
+}
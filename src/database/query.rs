@@ -7,6 +7,9 @@ use bson::{Bson, Document};
 
 use utils::bson::BsonNumber;
 
+pub use super::parse::ParseError;
+pub use super::validate::{QueryProblem, QueryProblemKind, ValidationError};
+
 /// A container of EJDB query options.
 ///
 /// This structure is a wrapper around a BSON document with various options affecting query
@@ -912,6 +915,42 @@ impl Q {
     }
 }
 
+/// A named placeholder for a value in a query.
+///
+/// Placeholders can be used in queries and query hints in place of any value, including
+/// numbers. Such queries must be executed with `CompiledQuery`, which substitutes the bound
+/// values for the placeholders before executing the query. In the query document,
+/// a placeholder is represented as a `{ "$param": name }` subdocument.
+///
+/// # Example
+///
+/// ```no_run
+/// # use ejdb::Database;
+/// use ejdb::query::{Param, Q, QH};
+///
+/// let db = Database::open("/path/to/db").unwrap();
+/// let coll = db.collection("some_collection").unwrap();
+/// let mut query = coll.compile(Q.field("age").gt(Param("min")), QH.empty()).unwrap();
+/// let n = query.bind("min", 18).count().unwrap();
+/// ```
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Param<S>(pub S);
+
+pub(crate) const PARAM_KEY: &'static str = "$param";
+
+impl<S: Into<String>> From<Param<S>> for Bson {
+    fn from(param: Param<S>) -> Bson {
+        bson!(PARAM_KEY => (param.0.into())).into()
+    }
+}
+
+impl<S: Into<String>> BsonNumber for Param<S> {
+    #[inline]
+    fn to_bson(self) -> Bson {
+        self.into()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use bson::oid::ObjectId;
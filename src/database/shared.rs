@@ -0,0 +1,513 @@
+//! Thread-safe database and collection handles.
+//!
+//! `Collection` objects borrow the `Database` they belong to, so they can't be stored
+//! in long-lived structures or sent to other threads. This module provides `SharedDatabase`,
+//! a reference-counted database handle protected by a lock, and `CollectionHandle`,
+//! an owned collection handle which can be freely cloned and sent between threads.
+//!
+//! # Thread safety
+//!
+//! `SharedDatabase` and `CollectionHandle` are both `Send` and `Sync`. Every operation
+//! acquires the database lock for its whole duration, so operations issued from different
+//! threads never run concurrently, even if they target different collections. Closures
+//! passed to `SharedDatabase::with()`, `CollectionHandle::with()`,
+//! `CollectionHandle::transaction()` and `CollectionHandle::index()` run while the lock is held;
+//! they must not use other handles to the same database, otherwise they will deadlock.
+//!
+//! Results of the operations, including `QueryResult` objects, do not depend on the lock
+//! and can be used after it is released.
+//!
+//! If a thread panics while holding the lock, the lock becomes poisoned, and all subsequent
+//! operations through any handle to the database fail with an error: the panic may have
+//! interrupted a sequence of operations which was supposed to be completed as a whole.
+
+use std::borrow::Borrow;
+use std::io;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use bson::{oid, Document};
+use ejdb_sys;
+
+use super::indices::Index;
+use super::open_mode::DatabaseOpenMode;
+use super::query;
+use super::{Collection, CollectionOptions, Database, PreparedQuery, QueryResult};
+use Result;
+
+/// A database handle which can be shared between threads.
+///
+/// This is a reference-counted wrapper around `Database` protected by a mutex. Cloning
+/// a `SharedDatabase` is cheap and produces another handle to the same database; the database
+/// is closed when the last handle, including all `CollectionHandle`s, is dropped.
+///
+/// See the module documentation for the details on thread safety.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[macro_use] extern crate ejdb;
+/// use std::thread;
+/// use ejdb::SharedDatabase;
+///
+/// # fn main() {
+/// let db = SharedDatabase::open("/path/to/db").unwrap();
+/// let coll = db.collection("some_collection").unwrap();
+/// let worker = thread::spawn(move || {
+///     coll.save(bson! { "name" => "Foo" }).unwrap()
+/// });
+/// let id = worker.join().unwrap();
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct SharedDatabase(Arc<Mutex<Database>>);
+
+impl SharedDatabase {
+    /// Opens the specified database with the provided open mode.
+    ///
+    /// # Failures
+    ///
+    /// Fails in the same cases as `Database::open_with_mode()`.
+    #[inline]
+    pub fn open_with_mode<P: Into<Vec<u8>>>(
+        path: P,
+        open_mode: DatabaseOpenMode,
+    ) -> Result<SharedDatabase> {
+        Database::open_with_mode(path, open_mode).map(SharedDatabase::new)
+    }
+
+    /// Opens the specified database with the default open mode.
+    ///
+    /// # Failures
+    ///
+    /// Fails in the same cases as `Database::open()`.
+    #[inline]
+    pub fn open<P: Into<Vec<u8>>>(path: P) -> Result<SharedDatabase> {
+        Database::open(path).map(SharedDatabase::new)
+    }
+
+    /// Wraps the provided database into a shared handle.
+    #[inline]
+    pub fn new(db: Database) -> SharedDatabase {
+        SharedDatabase(Arc::new(Mutex::new(db)))
+    }
+
+    /// Acquires the database lock, returning a guard which provides access to the database.
+    ///
+    /// The lock is held until the guard is dropped.
+    ///
+    /// # Failures
+    ///
+    /// Returns an error if the lock is poisoned, that is, if a thread has panicked while
+    /// holding it.
+    pub fn lock(&self) -> Result<MutexGuard<Database>> {
+        self.0
+            .lock()
+            .map_err(|_| "database lock is poisoned by a panicked thread".into())
+    }
+
+    /// Runs the provided function with the database while holding the lock.
+    ///
+    /// # Failures
+    ///
+    /// Returns an error if the lock is poisoned, or the error returned by the function.
+    pub fn with<F, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&Database) -> Result<T>,
+    {
+        let db = try!(self.lock());
+        f(&db)
+    }
+
+    /// Returns a handle for a collection by its name, creating the collection if needed.
+    ///
+    /// See `Database::collection()` for more information.
+    ///
+    /// # Failures
+    ///
+    /// Fails in the same cases as `Database::collection()`.
+    pub fn collection<S: Into<String>>(&self, name: S) -> Result<CollectionHandle> {
+        self.collection_with_options(name, CollectionOptions::default())
+    }
+
+    /// Returns a handle for a collection by its name, creating the collection with the
+    /// provided options if needed.
+    ///
+    /// See `Database::collection_with_options()` for more information.
+    ///
+    /// # Failures
+    ///
+    /// Fails in the same cases as `Database::collection_with_options()`.
+    pub fn collection_with_options<S: Into<String>>(
+        &self,
+        name: S,
+        options: CollectionOptions,
+    ) -> Result<CollectionHandle> {
+        let name = name.into();
+        let cached = try!(self.with(|db| {
+            db.collection_with_options(&*name, options)
+                .map(|coll| CachedCollection::new(db, &coll))
+        }));
+        Ok(CollectionHandle::new(self.clone(), name, cached))
+    }
+
+    /// Returns a handle for an existing collection by its name.
+    ///
+    /// See `Database::get_collection()` for more information.
+    ///
+    /// # Failures
+    ///
+    /// Fails in the same cases as `Database::get_collection()`.
+    pub fn get_collection<S: Into<String>>(&self, name: S) -> Result<Option<CollectionHandle>> {
+        let name = name.into();
+        let cached = try!(self.with(|db| {
+            db.get_collection(&*name)
+                .map(|coll| coll.map(|coll| CachedCollection::new(db, &coll)))
+        }));
+        Ok(cached.map(|cached| CollectionHandle::new(self.clone(), name, cached)))
+    }
+}
+
+impl From<Database> for SharedDatabase {
+    #[inline]
+    fn from(db: Database) -> SharedDatabase {
+        SharedDatabase::new(db)
+    }
+}
+
+/// An owned handle to a collection of a `SharedDatabase`.
+///
+/// Unlike `Collection`, this handle does not borrow the database, so it can be stored
+/// in long-lived structures, cloned and sent between threads. It provides the same
+/// save, load, query, transaction and index operations as `Collection`; each of them
+/// locks the database for its duration.
+///
+/// If the collection is removed from the database, all operations on its handles fail.
+///
+/// `CollectionHandle`s are obtained with `SharedDatabase::collection()` and similar methods.
+#[derive(Clone)]
+pub struct CollectionHandle {
+    db: SharedDatabase,
+    name: Arc<str>,
+    // only accessed while holding the database lock
+    cached: Arc<Mutex<CachedCollection>>,
+}
+
+// A collection structure together with the database collections generation it is valid for.
+#[derive(Copy, Clone)]
+struct CachedCollection {
+    generation: u64,
+    coll: *mut ejdb_sys::EJCOLL,
+}
+
+// The structure is only dereferenced while holding the database lock.
+unsafe impl Send for CachedCollection {}
+
+impl CachedCollection {
+    fn new(db: &Database, coll: &Collection) -> CachedCollection {
+        CachedCollection {
+            generation: db.collections_generation(),
+            coll: coll.coll,
+        }
+    }
+}
+
+impl CollectionHandle {
+    fn new(db: SharedDatabase, name: String, cached: CachedCollection) -> CollectionHandle {
+        CollectionHandle {
+            db: db,
+            name: name.into(),
+            cached: Arc::new(Mutex::new(cached)),
+        }
+    }
+
+    /// Returns the name of the collection.
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the database this collection belongs to.
+    #[inline]
+    pub fn database(&self) -> &SharedDatabase {
+        &self.db
+    }
+
+    /// Runs the provided function with the collection while holding the database lock.
+    ///
+    /// # Failures
+    ///
+    /// Returns an error if the database lock is poisoned, if the collection does not exist
+    /// anymore or if it can't be obtained, or the error returned by the function.
+    pub fn with<F, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&Collection) -> Result<T>,
+    {
+        let db = try!(self.db.lock());
+        let coll = try!(self.collection(&db));
+        f(&coll)
+    }
+
+    // The collection is looked up by name only if it may have been dropped since
+    // the last lookup; otherwise the cached collection structure is reused.
+    fn collection<'db>(&self, db: &'db Database) -> Result<Collection<'db>> {
+        let mut cached = try!(self
+            .cached
+            .lock()
+            .map_err(|_| "collection lock is poisoned by a panicked thread"));
+        if cached.generation != db.collections_generation() {
+            match try!(db.get_collection(&*self.name)) {
+                Some(coll) => *cached = CachedCollection::new(db, &coll),
+                None => return Err(format!("collection {} does not exist", self.name).into()),
+            }
+        }
+        Ok(Collection {
+            coll: cached.coll,
+            db: db,
+            _copy: None,
+        })
+    }
+
+    /// Saves the document to the collection.
+    ///
+    /// See `Collection::save()` for more information.
+    #[inline]
+    pub fn save<D: Borrow<Document>>(&self, doc: D) -> Result<oid::ObjectId> {
+        self.with(|coll| coll.save(doc))
+    }
+
+    /// Saves all documents from the provided iterator to the collection.
+    ///
+    /// See `Collection::save_all()` for more information.
+    #[inline]
+    pub fn save_all<I>(&self, docs: I) -> Result<Vec<oid::ObjectId>>
+    where
+        I: IntoIterator,
+        I::Item: Borrow<Document>,
+    {
+        self.with(|coll| coll.save_all(docs))
+    }
+
+    /// Loads the document from the collection by its id.
+    ///
+    /// See `Collection::load()` for more information.
+    #[inline]
+    pub fn load(&self, id: &oid::ObjectId) -> Result<Option<Document>> {
+        self.with(|coll| coll.load(id))
+    }
+
+    /// Removes the document from the collection by its id.
+    ///
+    /// See `Collection::remove()` for more information.
+    #[inline]
+    pub fn remove(&self, id: &oid::ObjectId) -> Result<bool> {
+        self.with(|coll| coll.remove(id))
+    }
+
+    /// Removes all documents with the provided ids from the collection.
+    ///
+    /// See `Collection::remove_all()` for more information.
+    #[inline]
+    pub fn remove_all<I>(&self, ids: I) -> Result<Vec<bool>>
+    where
+        I: IntoIterator,
+        I::Item: Borrow<oid::ObjectId>,
+    {
+        self.with(|coll| coll.remove_all(ids))
+    }
+
+    /// Prepares a query over the collection.
+    ///
+    /// The database is locked only when the query is executed. See `Collection::query()`
+    /// for more information.
+    #[inline]
+    pub fn query<Q, H>(&self, query: Q, hints: H) -> HandleQuery<Q, H>
+    where
+        Q: Borrow<query::Query>,
+        H: Borrow<query::QueryHints>,
+    {
+        HandleQuery {
+            coll: self,
+            query: query,
+            hints: hints,
+            or_queries: Vec::new(),
+            log_out: None,
+            validate: cfg!(debug_assertions),
+        }
+    }
+
+    /// Runs the provided function inside a transaction over the collection.
+    ///
+    /// The transaction is committed if the function returns `Ok`, and it is aborted if
+    /// the function returns an error or panics. The database lock is held while the function
+    /// is running, so transactions never interleave with operations from other handles.
+    ///
+    /// # Failures
+    ///
+    /// Returns an error if the transaction can't be started or committed, or the error
+    /// returned by the function.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # #[macro_use] extern crate ejdb;
+    /// # use ejdb::SharedDatabase;
+    /// # fn main() {
+    /// let db = SharedDatabase::open("/path/to/db").unwrap();
+    /// let coll = db.collection("some_collection").unwrap();
+    /// let (a, b) = coll.transaction(|coll| {
+    ///     let a = coll.save(bson! { "name" => "Foo" })?;
+    ///     let b = coll.save(bson! { "name" => "Bar" })?;
+    ///     Ok((a, b))
+    /// }).unwrap();
+    /// # }
+    /// ```
+    pub fn transaction<F, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&Collection) -> Result<T>,
+    {
+        self.with(|coll| {
+            let tx = try!(coll.begin_transaction());
+            // the transaction is aborted when dropped, including unwinding
+            let result = try!(f(coll));
+            try!(tx.commit());
+            Ok(result)
+        })
+    }
+
+    /// Checks whether there is an active transaction on the collection.
+    ///
+    /// See `Collection::transaction_active()` for more information.
+    #[inline]
+    pub fn transaction_active(&self) -> Result<bool> {
+        self.with(|coll| coll.transaction_active())
+    }
+
+    /// Runs the provided function with an index builder for the provided field in
+    /// the collection while holding the database lock.
+    ///
+    /// See `Collection::index()` for more information.
+    ///
+    /// # Failures
+    ///
+    /// Returns an error if the collection can't be accessed, or the error returned by
+    /// the function.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use ejdb::SharedDatabase;
+    /// let db = SharedDatabase::open("/path/to/db").unwrap();
+    /// let coll = db.collection("some_collection").unwrap();
+    /// coll.index("name", |index| index.string(true).set()).unwrap();
+    /// ```
+    pub fn index<S, F, T>(&self, key: S, f: F) -> Result<T>
+    where
+        S: Into<String>,
+        F: FnOnce(Index) -> Result<T>,
+    {
+        self.with(|coll| f(coll.index(key)))
+    }
+}
+
+/// A query prepared for execution over a `CollectionHandle`.
+///
+/// This is a counterpart of `PreparedQuery` which locks the database only for the duration
+/// of the query execution. It is created with `CollectionHandle::query()` method.
+pub struct HandleQuery<'h, 'out, Q, H> {
+    coll: &'h CollectionHandle,
+    query: Q,
+    hints: H,
+    or_queries: Vec<query::Query>,
+    log_out: Option<&'out mut io::Write>,
+    validate: bool,
+}
+
+impl<'h, 'out, Q, H> HandleQuery<'h, 'out, Q, H>
+where
+    Q: Borrow<query::Query>,
+    H: Borrow<query::QueryHints>,
+{
+    /// Sets the provided writer as a logging target for this query.
+    ///
+    /// See `PreparedQuery::log_output()` for more information.
+    pub fn log_output<'o>(self, target: &'o mut (io::Write + 'o)) -> HandleQuery<'h, 'o, Q, H> {
+        HandleQuery {
+            coll: self.coll,
+            query: self.query,
+            hints: self.hints,
+            or_queries: self.or_queries,
+            log_out: Some(target),
+            validate: self.validate,
+        }
+    }
+
+    /// Attaches an additional query to this one, joined with OR.
+    ///
+    /// See `PreparedQuery::or_query()` for more information.
+    pub fn or_query(mut self, query: query::Query) -> Self {
+        self.or_queries.push(query);
+        self
+    }
+
+    /// Enables validation of this query before it is executed.
+    ///
+    /// See `PreparedQuery::validated()` for more information.
+    pub fn validated(mut self) -> Self {
+        self.validate = true;
+        self
+    }
+
+    /// Executes the query, returning the number of affected records.
+    ///
+    /// See `PreparedQuery::count()` for more information.
+    pub fn count(self) -> Result<u32> {
+        self.run(|q| q.count())
+    }
+
+    /// Executes the query which is expected to update records, returning the number
+    /// of updated records.
+    ///
+    /// See `PreparedQuery::update()` for more information.
+    pub fn update(self) -> Result<u32> {
+        self.run(|q| q.update())
+    }
+
+    /// Executes the query, returning the first matched element if it is available.
+    ///
+    /// See `PreparedQuery::find_one()` for more information.
+    pub fn find_one(self) -> Result<Option<Document>> {
+        self.run(|q| q.find_one())
+    }
+
+    /// Executes the query, returning an iterator of all documents matching it.
+    ///
+    /// The returned iterator does not hold the database lock. See `PreparedQuery::find()`
+    /// for more information.
+    pub fn find(self) -> Result<QueryResult> {
+        self.run(|q| q.find())
+    }
+
+    fn run<F, T>(self, f: F) -> Result<T>
+    where
+        F: for<'c, 'db> FnOnce(PreparedQuery<'c, 'db, 'out, Q, H>) -> Result<T>,
+    {
+        let HandleQuery {
+            coll,
+            query,
+            hints,
+            or_queries,
+            log_out,
+            validate,
+        } = self;
+        coll.with(move |coll| {
+            f(PreparedQuery {
+                coll: coll,
+                query: query,
+                hints: hints,
+                or_queries: or_queries,
+                log_out: log_out,
+                validate: validate,
+            })
+        })
+    }
+}
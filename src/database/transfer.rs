@@ -0,0 +1,331 @@
+//! Types used by export and import methods on `Database` structure.
+//!
+//! EJDB can dump its collections into a directory, one file per collection plus a file with
+//! collection metadata, and then load such a dump back, into the same or another database.
+//! `Database::export()` and `Database::import()` methods provide access to this functionality;
+//! they return a `TransferReport` which describes which collections have been processed
+//! and how many records have been exported or imported.
+
+use std::ffi::CString;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+use bson::Bson;
+use libc::c_int;
+
+use ejdb_sys;
+
+use super::parse::parse_document;
+use super::Database;
+use utils::tclist::TCList;
+use utils::tcxstr::TCXString;
+use Result;
+
+/// Represents a format of exported collection files.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ExportFormat {
+    /// Collections are exported as raw BSON files.
+    Bson,
+    /// Collections are exported as JSON files.
+    Json,
+}
+
+impl ExportFormat {
+    pub(crate) fn flags(self) -> u32 {
+        match self {
+            ExportFormat::Bson => 0,
+            ExportFormat::Json => ejdb_sys::JBJSONEXPORT,
+        }
+    }
+}
+
+/// Represents a way of handling existing collections when importing data.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ImportMode {
+    /// Imported records are merged into existing collections; records with the same ids
+    /// are replaced with the imported ones.
+    Update,
+    /// Existing collections are dropped and replaced with the imported ones entirely.
+    Replace,
+}
+
+impl ImportMode {
+    pub(crate) fn flags(self) -> u32 {
+        match self {
+            ImportMode::Update => ejdb_sys::JBIMPORTUPDATE,
+            ImportMode::Replace => ejdb_sys::JBIMPORTREPLACE,
+        }
+    }
+}
+
+impl Database {
+    /// Exports the specified collections into the provided directory.
+    ///
+    /// `path` is a path to the target directory, which will be created if needed. It may be
+    /// of any type convertible to a vector of bytes. `collections` is an iterable of collection
+    /// names; if it is empty, all collections of the database are exported. `format`
+    /// determines whether the collections are written as BSON or JSON files.
+    ///
+    /// # Failures
+    ///
+    /// Returns an error if `path` or any of the collection names contain zero bytes, if
+    /// the corresponding EJDB operation can't be completed successfully or if the metadata
+    /// files written by it can't be read.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use ejdb::Database;
+    /// use ejdb::transfer::ExportFormat;
+    ///
+    /// let db = Database::open("/path/to/db").unwrap();
+    /// let report = db.export("/path/to/backup", vec!["some_collection"], ExportFormat::Json)
+    ///     .unwrap();
+    /// println!("{}", report);
+    /// ```
+    pub fn export<P, I>(
+        &self,
+        path: P,
+        collections: I,
+        format: ExportFormat,
+    ) -> Result<TransferReport>
+    where
+        P: Into<Vec<u8>>,
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        let path = try!(CString::new(path).map_err(|_| "invalid path specified"));
+        let names: Vec<String> = collections.into_iter().map(Into::into).collect();
+        let cnames = try!(names_list(&names));
+        let log = TCXString::new();
+
+        let result = unsafe {
+            ejdb_sys::ejdbexport(
+                self.0,
+                path.as_ptr(),
+                cnames
+                    .as_ref()
+                    .map(|l| l.as_raw())
+                    .unwrap_or(ptr::null_mut()),
+                format.flags() as c_int,
+                log.as_raw(),
+            )
+        };
+        if !result {
+            return self.last_error("cannot export collections");
+        }
+
+        let names = if names.is_empty() {
+            try!(self.get_metadata())
+                .collections()
+                .map(|c| c.name().to_owned())
+                .collect()
+        } else {
+            names
+        };
+        self.transfer_report(path, names, &log)
+    }
+
+    /// Imports the specified collections from the provided directory.
+    ///
+    /// `path` is a path to a directory created by `Database::export()`; it may be of any type
+    /// convertible to a vector of bytes. `collections` is an iterable of collection names;
+    /// if it is empty, all collections whose metadata files are found in the directory are
+    /// imported. `mode` determines what happens with the collections which already exist
+    /// in this database.
+    ///
+    /// # Failures
+    ///
+    /// Returns an error if `path` or any of the collection names contain zero bytes, if the
+    /// directory or the metadata files in it can't be read or if the corresponding EJDB
+    /// operation can't be completed successfully.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use ejdb::Database;
+    /// use ejdb::transfer::ImportMode;
+    ///
+    /// let db = Database::open("/path/to/db").unwrap();
+    /// let report = db.import("/path/to/backup", Vec::<String>::new(), ImportMode::Replace)
+    ///     .unwrap();
+    /// for coll in report.collections() {
+    ///     println!("{}: {} records", coll.name, coll.records);
+    /// }
+    /// ```
+    pub fn import<P, I>(&self, path: P, collections: I, mode: ImportMode) -> Result<TransferReport>
+    where
+        P: Into<Vec<u8>>,
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        let path = try!(CString::new(path).map_err(|_| "invalid path specified"));
+        let names: Vec<String> = collections.into_iter().map(Into::into).collect();
+        let cnames = try!(names_list(&names));
+        let log = TCXString::new();
+
+        self.invalidate_collections();
+        let result = unsafe {
+            ejdb_sys::ejdbimport(
+                self.0,
+                path.as_ptr(),
+                cnames
+                    .as_ref()
+                    .map(|l| l.as_raw())
+                    .unwrap_or(ptr::null_mut()),
+                mode.flags() as c_int,
+                log.as_raw(),
+            )
+        };
+        if !result {
+            return self.last_error("cannot import collections");
+        }
+
+        let names = if names.is_empty() {
+            try!(exported_collection_names(&dump_path(&path)))
+        } else {
+            names
+        };
+        self.transfer_report(path, names, &log)
+    }
+
+    fn transfer_report(
+        &self,
+        path: CString,
+        names: Vec<String>,
+        log: &TCXString,
+    ) -> Result<TransferReport> {
+        let dir = dump_path(&path);
+        let mut collections = Vec::with_capacity(names.len());
+        for name in names {
+            let records = try!(exported_records(&dir, &name));
+            collections.push(CollectionReport {
+                name: name,
+                records: records,
+            });
+        }
+
+        Ok(TransferReport {
+            path: path.to_string_lossy().into_owned(),
+            collections: collections,
+            log: String::from_utf8_lossy(log).into_owned(),
+        })
+    }
+}
+
+// EJDB writes `<name>.bson` or `<name>.json` data files together with `<name>-meta.json` files
+// containing collection metadata; the latter are used by EJDB import to find the collections
+const META_SUFFIX: &'static str = "-meta.json";
+
+fn dump_path(path: &CString) -> PathBuf {
+    PathBuf::from(path.to_string_lossy().into_owned())
+}
+
+fn names_list(names: &[String]) -> Result<Option<TCList>> {
+    if names.is_empty() {
+        return Ok(None);
+    }
+
+    let mut list = TCList::new();
+    for name in names {
+        let name = try!(CString::new(name.as_bytes()).map_err(|_| "invalid collection name"));
+        list.push_str(&name);
+    }
+    Ok(Some(list))
+}
+
+fn exported_collection_names(dir: &Path) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    for entry in try!(fs::read_dir(dir)) {
+        let file_name = try!(entry).file_name();
+        let file_name = file_name.to_string_lossy();
+        if file_name.ends_with(META_SUFFIX) {
+            names.push(file_name[..file_name.len() - META_SUFFIX.len()].to_owned());
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+// the metadata is written at export time, so it contains the number of exported records
+fn exported_records(dir: &Path, name: &str) -> Result<u64> {
+    let mut data = String::new();
+    let mut file = try!(File::open(dir.join(format!("{}{}", name, META_SUFFIX))));
+    try!(file.read_to_string(&mut data));
+    let meta = try!(parse_document(&data));
+    match meta.get("records") {
+        Some(&Bson::I32(n)) if n >= 0 => Ok(n as u64),
+        Some(&Bson::I64(n)) if n >= 0 => Ok(n as u64),
+        _ => Err(format!("invalid metadata of exported collection {}", name).into()),
+    }
+}
+
+/// A report about an export or import operation.
+///
+/// Returned by `Database::export()` and `Database::import()` methods. It contains the
+/// list of processed collections with the number of exported or imported records in each
+/// of them, as well as the log of the operation produced by EJDB.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct TransferReport {
+    path: String,
+    collections: Vec<CollectionReport>,
+    log: String,
+}
+
+impl TransferReport {
+    /// Returns the path to the directory which was used for the operation.
+    #[inline]
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Returns reports for each of the processed collections.
+    #[inline]
+    pub fn collections(&self) -> &[CollectionReport] {
+        &self.collections
+    }
+
+    /// Returns the total number of exported or imported records in all processed collections.
+    pub fn total_records(&self) -> u64 {
+        self.collections.iter().map(|c| c.records).sum()
+    }
+
+    /// Returns the log of the operation as reported by EJDB.
+    #[inline]
+    pub fn log(&self) -> &str {
+        &self.log
+    }
+}
+
+impl fmt::Display for TransferReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(writeln!(
+            f,
+            "{}: {} collection(s), {} record(s)",
+            self.path,
+            self.collections.len(),
+            self.total_records()
+        ));
+        for coll in &self.collections {
+            try!(writeln!(f, "    {}: {} record(s)", coll.name, coll.records));
+        }
+        Ok(())
+    }
+}
+
+/// A part of `TransferReport` describing one processed collection.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct CollectionReport {
+    /// The name of the collection.
+    pub name: String,
+    /// The number of records in the exported collection file.
+    ///
+    /// This number is read from the collection metadata written by the export, so for imports
+    /// it is the number of records read from the dump. In `ImportMode::Update` mode some
+    /// of them may have replaced existing records, so the number of records in the collection
+    /// after the import may differ.
+    pub records: u64,
+}
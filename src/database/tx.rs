@@ -1,7 +1,10 @@
+use std::thread;
+use std::time::Duration;
+
 use ejdb_sys;
 
 use super::Collection;
-use Result;
+use {Error, Result};
 
 impl<'db> Collection<'db> {
     /// Starts a transaction, returning a guard object for it.
@@ -51,6 +54,204 @@ impl<'db> Collection<'db> {
             self.db.last_error("error getting transaction status")
         }
     }
+
+    /// Runs the provided function inside a transaction over this collection, retrying it
+    /// according to the default `RetryPolicy`.
+    ///
+    /// See `Collection::transaction_with_retry()` for more information.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # #[macro_use] extern crate ejdb;
+    /// # use ejdb::Database;
+    /// # fn main() {
+    /// let db = Database::open("/path/to/db").unwrap();
+    /// let coll = db.collection("some_collection").unwrap();
+    /// let (a, b) = coll.transaction(|coll| {
+    ///     let a = coll.save(bson! { "name" => "Foo" })?;
+    ///     let b = coll.save(bson! { "name" => "Bar" })?;
+    ///     Ok((a, b))
+    /// }).unwrap();
+    /// # }
+    /// ```
+    #[inline]
+    pub fn transaction<F, T>(&self, f: F) -> Result<T>
+    where
+        F: FnMut(&Collection<'db>) -> Result<T>,
+    {
+        self.transaction_with_retry(RetryPolicy::default(), f)
+    }
+
+    /// Runs the provided function inside a transaction over this collection, retrying it
+    /// according to the provided policy.
+    ///
+    /// The transaction is committed if the function returns `Ok`, and it is aborted if
+    /// the function returns an error or panics. If the function returns a lock error
+    /// (see `Error::is_lock_error()`) or if the commit fails, the whole transaction, including
+    /// the function call, is retried after a delay, until the number of attempts allowed
+    /// by the policy is exhausted. Therefore the function should not have side effects other
+    /// than operations over the collection.
+    ///
+    /// Starting a transaction is never retried: EJDB waits for other transactions on
+    /// the collection to complete instead of reporting contention.
+    ///
+    /// # Failures
+    ///
+    /// Returns the error of the last attempt: either the error returned by the function,
+    /// or an error starting or committing the transaction. Errors which are not retried
+    /// are returned immediately. The transaction is aborted before the delay, so other
+    /// threads can use the collection while this one is waiting. If a failed transaction
+    /// can't be aborted, it is not retried, and the returned error also describes
+    /// the abort failure.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # #[macro_use] extern crate ejdb;
+    /// # use std::time::Duration;
+    /// # use ejdb::Database;
+    /// use ejdb::RetryPolicy;
+    ///
+    /// # fn main() {
+    /// let db = Database::open("/path/to/db").unwrap();
+    /// let coll = db.collection("some_collection").unwrap();
+    /// let policy = RetryPolicy::new().attempts(5).backoff(Duration::from_millis(50));
+    /// let id = coll.transaction_with_retry(policy, |coll| {
+    ///     coll.save(bson! { "name" => "Foo" })
+    /// }).unwrap();
+    /// # }
+    /// ```
+    pub fn transaction_with_retry<F, T>(&self, policy: RetryPolicy, mut f: F) -> Result<T>
+    where
+        F: FnMut(&Collection<'db>) -> Result<T>,
+    {
+        let mut attempt = 1;
+        loop {
+            let tx = try!(self.begin_transaction());
+            let (error, retry) = match f(self) {
+                Ok(value) => match tx.commit() {
+                    Ok(()) => return Ok(value),
+                    // make sure that the failed transaction does not stay active
+                    Err(e) => match self.abort_failed_commit() {
+                        Ok(()) => (e, true),
+                        Err(cleanup) => (with_cleanup_error(e, cleanup), false),
+                    },
+                },
+                // the transaction must not stay active during the delay before a retry;
+                // it is also aborted when dropped during unwinding
+                Err(e) => match tx.abort() {
+                    Ok(()) => {
+                        let retry = e.is_lock_error();
+                        (e, retry)
+                    }
+                    Err(cleanup) => (with_cleanup_error(e, cleanup), false),
+                },
+            };
+            if !retry || attempt >= policy.attempts {
+                return Err(error);
+            }
+            thread::sleep(policy.delay(attempt));
+            attempt += 1;
+        }
+    }
+
+    fn abort_failed_commit(&self) -> Result<()> {
+        if try!(self.transaction_active()) {
+            Transaction::existing(self).abort()
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Adds the description of a failed cleanup to the original error, preserving its kind.
+fn with_cleanup_error(error: Error, cleanup: Error) -> Error {
+    match error {
+        Error::Ejdb {
+            code,
+            kind,
+            context,
+        } => Error::Ejdb {
+            code: code,
+            kind: kind,
+            context: format!("{} (aborting the transaction failed: {})", context, cleanup).into(),
+        },
+        error => format!("{} (aborting the transaction failed: {})", error, cleanup).into(),
+    }
+}
+
+/// Determines how transactions started with `Collection::transaction_with_retry()` are retried.
+///
+/// A policy consists of the maximum number of attempts and the delay before the first
+/// retry; the delay is doubled before each subsequent retry. The default policy makes
+/// 3 attempts with the initial delay of 10 milliseconds.
+///
+/// # Example
+///
+/// ```
+/// # use std::time::Duration;
+/// use ejdb::RetryPolicy;
+///
+/// let policy = RetryPolicy::new().attempts(5).backoff(Duration::from_millis(20));
+/// assert_eq!(policy.max_attempts(), 5);
+/// assert_eq!(RetryPolicy::never().max_attempts(), 1);
+/// ```
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct RetryPolicy {
+    attempts: u32,
+    backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            attempts: 3,
+            backoff: Duration::from_millis(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Creates the default retry policy.
+    #[inline]
+    pub fn new() -> RetryPolicy {
+        RetryPolicy::default()
+    }
+
+    /// Creates a policy which makes a single attempt, never retrying.
+    #[inline]
+    pub fn never() -> RetryPolicy {
+        RetryPolicy::new().attempts(1)
+    }
+
+    /// Sets the maximum number of attempts, including the first one.
+    ///
+    /// Zero is treated as one, i.e. the transaction is always attempted at least once.
+    #[inline]
+    pub fn attempts(mut self, attempts: u32) -> RetryPolicy {
+        self.attempts = attempts.max(1);
+        self
+    }
+
+    /// Sets the delay before the first retry.
+    #[inline]
+    pub fn backoff(mut self, backoff: Duration) -> RetryPolicy {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Returns the maximum number of attempts.
+    #[inline]
+    pub fn max_attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// Returns the delay after the given failed attempt, counting from one.
+    fn delay(&self, attempt: u32) -> Duration {
+        let factor = 1u32 << (attempt - 1).min(16);
+        self.backoff.checked_mul(factor).unwrap_or(self.backoff)
+    }
 }
 
 /// Represents an active transaction.
@@ -91,13 +292,18 @@ impl<'coll, 'db> Drop for Transaction<'coll, 'db> {
 impl<'coll, 'db> Transaction<'coll, 'db> {
     fn new(coll: &'coll Collection<'db>) -> Result<Transaction<'coll, 'db>> {
         if unsafe { ejdb_sys::ejdbtranbegin(coll.coll) } {
-            coll.db.last_error("error opening transaction")
+            Ok(Transaction::existing(coll))
         } else {
-            Ok(Transaction {
-                coll: coll,
-                commit: false,
-                finished: false,
-            })
+            coll.db.last_error("error opening transaction")
+        }
+    }
+
+    /// Returns a guard for a transaction which is already active on the collection.
+    fn existing(coll: &'coll Collection<'db>) -> Transaction<'coll, 'db> {
+        Transaction {
+            coll: coll,
+            commit: false,
+            finished: false,
         }
     }
 
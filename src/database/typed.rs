@@ -0,0 +1,217 @@
+//! Collections of values of serializable types.
+//!
+//! `TypedCollection` wraps a `Collection` and converts values of a type implementing serde
+//! traits to BSON documents when they are saved and back when they are loaded or found,
+//! so the application can work with its own types instead of raw documents. It is obtained
+//! with `Database::typed_collection()` or `TypedCollection::new()`.
+//!
+//! A null `_id` field, e.g. a serialized `Option<ObjectId>` which is `None`, is removed
+//! before saving, so EJDB generates an id for the new record. Query results are deserialized
+//! lazily, one document at a time, as `TypedQueryResult` is traversed. Queries themselves are
+//! still built with `query::Q` and refer to the BSON field names.
+
+use std::borrow::Borrow;
+use std::marker::PhantomData;
+
+use bson::{self, oid, Bson, Document};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use super::{query, Collection, Database, QueryResult};
+use Result;
+
+impl Database {
+    /// Returns a typed handle for a collection by its name, creating the collection if needed.
+    ///
+    /// This is a typed counterpart of `Database::collection()`: the returned collection
+    /// converts values of type `T` to BSON documents and back using serde.
+    ///
+    /// # Failures
+    ///
+    /// Fails in the same cases as `Database::collection()`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// #[macro_use] extern crate serde_derive;
+    /// # extern crate ejdb;
+    /// use ejdb::Database;
+    /// use ejdb::bson::oid::ObjectId;
+    ///
+    /// #[derive(Serialize, Deserialize)]
+    /// struct User {
+    ///     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    ///     id: Option<ObjectId>,
+    ///     name: String,
+    /// }
+    ///
+    /// # fn main() {
+    /// let db = Database::open("/path/to/db").unwrap();
+    /// let users = db.typed_collection::<User>("users").unwrap();
+    /// let id = users.save(&User { id: None, name: "Foo".into() }).unwrap();
+    /// let user = users.load(&id).unwrap().unwrap();
+    /// assert_eq!(user.id, Some(id));
+    /// # }
+    /// ```
+    #[inline]
+    pub fn typed_collection<T>(&self, name: &str) -> Result<TypedCollection<T>>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        self.collection(name).map(TypedCollection::new)
+    }
+}
+
+/// A handle to an EJDB collection which stores values of a serializable type.
+///
+/// This is a thin wrapper around `Collection` which converts values of type `T` to BSON
+/// documents when they are saved and back when they are loaded, using serde support
+/// of the `bson` crate.
+///
+/// EJDB stores the identifier of each record in the `_id` field. If `T` has a field of type
+/// `Option<bson::oid::ObjectId>` which is serialized as `_id`, it will be filled
+/// with the record identifier when a value is loaded. When a value is saved and this field
+/// is `None` (serialized either as a missing field or as `null`), a fresh identifier
+/// is generated; otherwise the existing record with this identifier is replaced.
+///
+/// `TypedCollection` instances can be created with `Database::typed_collection()` or
+/// `TypedCollection::new()` methods.
+pub struct TypedCollection<'db, T> {
+    coll: Collection<'db>,
+    _marker: PhantomData<fn(T) -> T>,
+}
+
+impl<'db, T> TypedCollection<'db, T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// Wraps the provided collection into a typed handle.
+    #[inline]
+    pub fn new(coll: Collection<'db>) -> TypedCollection<'db, T> {
+        TypedCollection {
+            coll: coll,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns a reference to the underlying untyped collection.
+    #[inline]
+    pub fn as_collection(&self) -> &Collection<'db> {
+        &self.coll
+    }
+
+    /// Consumes this typed handle, returning the underlying untyped collection.
+    #[inline]
+    pub fn into_collection(self) -> Collection<'db> {
+        self.coll
+    }
+
+    /// Saves the given value to this collection, returning the identifier of its record.
+    ///
+    /// See `Collection::save()` for more information.
+    ///
+    /// # Failures
+    ///
+    /// Returns an error if the value can't be serialized into a BSON document or if
+    /// the underlying save operation fails.
+    pub fn save<V: Borrow<T>>(&self, value: V) -> Result<oid::ObjectId> {
+        let doc = try!(to_document(value.borrow()));
+        self.coll.save(doc)
+    }
+
+    /// Attempts to load a value from this collection by its id.
+    ///
+    /// See `Collection::load()` for more information.
+    ///
+    /// # Failures
+    ///
+    /// Returns an error if the loaded document can't be deserialized into `T` or if
+    /// the underlying load operation fails.
+    pub fn load(&self, id: &oid::ObjectId) -> Result<Option<T>> {
+        match try!(self.coll.load(id)) {
+            Some(doc) => from_document(doc).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Executes the provided query, returning an iterator of all values matching it.
+    ///
+    /// See `PreparedQuery::find()` for more information.
+    ///
+    /// # Failures
+    ///
+    /// Fails in the same cases as `PreparedQuery::find()`. Each document from the query
+    /// is deserialized into `T` separately when the iterator is traversed.
+    pub fn find<Q, H>(&self, query: Q, hints: H) -> Result<TypedQueryResult<T>>
+    where
+        Q: Borrow<query::Query>,
+        H: Borrow<query::QueryHints>,
+    {
+        self.coll
+            .query(query, hints)
+            .find()
+            .map(|r| TypedQueryResult {
+                result: r,
+                _marker: PhantomData,
+            })
+    }
+
+    /// Executes the provided query, returning the first value matching it, if any.
+    ///
+    /// See `PreparedQuery::find_one()` for more information.
+    ///
+    /// # Failures
+    ///
+    /// Fails in the same cases as `PreparedQuery::find_one()` or if the found document can't
+    /// be deserialized into `T`.
+    pub fn find_one<Q, H>(&self, query: Q, hints: H) -> Result<Option<T>>
+    where
+        Q: Borrow<query::Query>,
+        H: Borrow<query::QueryHints>,
+    {
+        match try!(self.coll.query(query, hints).find_one()) {
+            Some(doc) => from_document(doc).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+fn to_document<T: Serialize>(value: &T) -> Result<Document> {
+    match try!(bson::to_bson(value)) {
+        Bson::Document(mut doc) => {
+            if let Some(&Bson::Null) = doc.get("_id") {
+                doc.remove("_id");
+            }
+            Ok(doc)
+        }
+        other => Err(format!("value is not serialized to a BSON document: {}", other).into()),
+    }
+}
+
+fn from_document<T: DeserializeOwned>(doc: Document) -> Result<T> {
+    bson::from_bson(Bson::Document(doc)).map_err(|e| e.into())
+}
+
+/// An iterator over EJDB query results converted to values of a serializable type.
+///
+/// Objects of this structure are returned by `TypedCollection::find()` method.
+pub struct TypedQueryResult<T> {
+    result: QueryResult,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> TypedQueryResult<T> {
+    /// Returns the number of records returned by the query.
+    #[inline]
+    pub fn count(&self) -> u32 {
+        QueryResult::count(&self.result)
+    }
+}
+
+impl<T: DeserializeOwned> Iterator for TypedQueryResult<T> {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Result<T>> {
+        self.result.next().map(|r| r.and_then(from_document))
+    }
+}
@@ -0,0 +1,503 @@
+//! Client-side validation of queries and query hints.
+//!
+//! `Query` and `QueryHints` can be built from arbitrary BSON documents, and EJDB reports
+//! problems in such documents with a single generic error. Validation finds the most common
+//! problems before the query is passed to EJDB and reports their exact location.
+
+use std::error;
+use std::fmt;
+
+use bson::{Bson, Document};
+use itertools::Itertools;
+
+use super::parse::{
+    ACTION_OPERATORS, FIELD_OPERATORS, HINT_OPERATORS, QUERY_OPERATORS, UPDATE_OPERATORS,
+};
+use super::query::{Query, QueryHints, PARAM_KEY};
+use Result;
+
+impl Query {
+    /// Checks this query for common problems.
+    ///
+    /// The following problems are detected:
+    ///
+    /// * unknown operators, i.e. keys starting with `$` which are not supported by EJDB
+    ///   in the corresponding position;
+    /// * `$bt` constraints whose value is not an array of exactly two numbers;
+    /// * `$in`, `$nin`, `$strand` and `$stror` constraints whose value is not an array;
+    /// * `$and` and `$or` values which are not arrays of documents;
+    /// * update operators whose value is not a document;
+    /// * illegally mixed update operators: `$dropall` together with other update operators
+    ///   or a field modified by several update operators.
+    ///
+    /// `PreparedQuery` runs this validation before execution in debug builds, and in release
+    /// builds if `PreparedQuery::validated()` has been called.
+    ///
+    /// # Failures
+    ///
+    /// Returns `Error::QueryValidation` containing all of the found problems.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[macro_use] extern crate ejdb;
+    /// use ejdb::query::{Query, QueryProblemKind};
+    /// use ejdb::Error;
+    ///
+    /// # fn main() {
+    /// let q = Query::from(bson! { "count" => { "$bt" => [1] } });
+    /// match q.validate() {
+    ///     Err(Error::QueryValidation(e)) => {
+    ///         assert_eq!(e.problems[0].path, "count.$bt");
+    ///         assert_eq!(e.problems[0].kind, QueryProblemKind::InvalidBetween);
+    ///     }
+    ///     _ => unreachable!(),
+    /// }
+    /// # }
+    /// ```
+    pub fn validate(&self) -> Result<()> {
+        let mut validator = Validator::new();
+        validator.query(self.as_bson());
+        validator.finish()
+    }
+}
+
+impl QueryHints {
+    /// Checks these query hints for common problems.
+    ///
+    /// The following problems are detected:
+    ///
+    /// * unknown keys;
+    /// * `$max` and `$skip` values which are not non-negative integers;
+    /// * `$orderby` values other than 1 and -1;
+    /// * `$fields` values mixing included and excluded fields.
+    ///
+    /// `PreparedQuery` runs this validation before execution in debug builds, and in release
+    /// builds if `PreparedQuery::validated()` has been called.
+    ///
+    /// # Failures
+    ///
+    /// Returns `Error::QueryValidation` containing all of the found problems.
+    pub fn validate(&self) -> Result<()> {
+        let mut validator = Validator::new();
+        validator.hints(self.as_bson());
+        validator.finish()
+    }
+}
+
+/// A kind of a problem found by query validation.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum QueryProblemKind {
+    /// An operator which is not supported in this position.
+    UnknownOperator(String),
+    /// `$bt` value is not an array of exactly two numbers.
+    InvalidBetween,
+    /// The value of the operator must be an array.
+    NotAnArray(String),
+    /// The value of the operator must be a document.
+    NotADocument(String),
+    /// Two update operators cannot be used together in the same query or for the same field.
+    MixedUpdateOperators(String, String),
+    /// `$orderby` value is not 1 or -1.
+    InvalidOrderBy,
+    /// The value of the hint must be a non-negative integer.
+    InvalidLimit(String),
+    /// `$fields` hint contains both included and excluded fields.
+    MixedFields,
+}
+
+impl fmt::Display for QueryProblemKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            QueryProblemKind::UnknownOperator(ref op) => write!(f, "unknown operator {}", op),
+            QueryProblemKind::InvalidBetween => {
+                write!(f, "$bt value must be an array of exactly two numbers")
+            }
+            QueryProblemKind::NotAnArray(ref op) => write!(f, "{} value must be an array", op),
+            QueryProblemKind::NotADocument(ref op) => {
+                write!(f, "{} value must be a document", op)
+            }
+            QueryProblemKind::MixedUpdateOperators(ref op1, ref op2) => {
+                write!(f, "{} cannot be used together with {}", op1, op2)
+            }
+            QueryProblemKind::InvalidOrderBy => write!(f, "$orderby value must be 1 or -1"),
+            QueryProblemKind::InvalidLimit(ref op) => {
+                write!(f, "{} value must be a non-negative integer", op)
+            }
+            QueryProblemKind::MixedFields => {
+                write!(f, "$fields cannot mix included and excluded fields")
+            }
+        }
+    }
+}
+
+/// A problem found by query validation.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct QueryProblem {
+    /// A dot-separated path to the problematic key in the query document, e.g. `count.$bt`.
+    ///
+    /// Array elements are denoted by their indices, e.g. `$or.1.name.$in`.
+    pub path: String,
+    /// The kind of the problem.
+    pub kind: QueryProblemKind,
+}
+
+impl fmt::Display for QueryProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.kind)
+    }
+}
+
+/// A query validation error returned by `Query::validate()` and `QueryHints::validate()`.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct ValidationError {
+    /// All problems found in the query, in the order of their appearance.
+    pub problems: Vec<QueryProblem>,
+}
+
+impl error::Error for ValidationError {
+    fn description(&self) -> &str {
+        "invalid query"
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.problems.iter().join("; "))
+    }
+}
+
+struct Validator {
+    path: Vec<String>,
+    problems: Vec<QueryProblem>,
+}
+
+impl Validator {
+    fn new() -> Validator {
+        Validator {
+            path: Vec::new(),
+            problems: Vec::new(),
+        }
+    }
+
+    fn finish(self) -> Result<()> {
+        if self.problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationError {
+                problems: self.problems,
+            }
+            .into())
+        }
+    }
+
+    fn report(&mut self, kind: QueryProblemKind) {
+        self.problems.push(QueryProblem {
+            path: self.path.join("."),
+            kind: kind,
+        });
+    }
+
+    fn at<K: ToString, F: FnOnce(&mut Validator)>(&mut self, key: K, f: F) {
+        self.path.push(key.to_string());
+        f(self);
+        self.path.pop();
+    }
+
+    fn query(&mut self, doc: &Document) {
+        for (key, value) in doc {
+            self.at(key, |v| {
+                if !key.starts_with('$') {
+                    v.field(value);
+                } else if QUERY_OPERATORS.contains(&&**key) {
+                    v.query_list(key, value);
+                } else if key == "$do" {
+                    v.actions(value);
+                } else if UPDATE_OPERATORS.contains(&&**key) {
+                    v.update(key, value);
+                } else {
+                    v.report(QueryProblemKind::UnknownOperator(key.clone()));
+                }
+            });
+        }
+        self.update_mix(doc);
+    }
+
+    fn query_list(&mut self, op: &str, value: &Bson) {
+        match *value {
+            Bson::Array(ref queries) => {
+                for (i, query) in queries.iter().enumerate() {
+                    self.at(i, |v| match *query {
+                        Bson::Document(ref doc) => v.query(doc),
+                        _ => v.report(QueryProblemKind::NotADocument(op.into())),
+                    });
+                }
+            }
+            _ => self.report(QueryProblemKind::NotAnArray(op.into())),
+        }
+    }
+
+    fn field(&mut self, value: &Bson) {
+        let doc = match *value {
+            Bson::Document(ref doc) if !is_param(value) => doc,
+            _ => return,
+        };
+        // a document without operators is matched as is
+        if !doc.keys().any(|k| k.starts_with('$')) {
+            return;
+        }
+        for (key, value) in doc {
+            self.at(key, |v| match &**key {
+                "$bt" => match *value {
+                    Bson::Array(ref arr) if arr.len() == 2 && arr.iter().all(is_number) => {}
+                    _ => v.report(QueryProblemKind::InvalidBetween),
+                },
+                "$in" | "$nin" | "$strand" | "$stror" => match *value {
+                    Bson::Array(_) => {}
+                    _ => v.report(QueryProblemKind::NotAnArray(key.clone())),
+                },
+                "$elemMatch" => match *value {
+                    Bson::Document(ref doc) => v.query(doc),
+                    _ => v.report(QueryProblemKind::NotADocument(key.clone())),
+                },
+                "$not" | "$icase" => v.field(value),
+                _ if FIELD_OPERATORS.contains(&&**key) => {}
+                _ => v.report(QueryProblemKind::UnknownOperator(key.clone())),
+            });
+        }
+    }
+
+    fn update(&mut self, op: &str, value: &Bson) {
+        if op == "$dropall" {
+            return;
+        }
+        match *value {
+            Bson::Document(ref doc) if op == "$pullAll" || op == "$pushAll" => {
+                for (key, value) in doc {
+                    match *value {
+                        Bson::Array(_) => {}
+                        _ => self.at(key, |v| v.report(QueryProblemKind::NotAnArray(op.into()))),
+                    }
+                }
+            }
+            Bson::Document(_) => {}
+            _ => self.report(QueryProblemKind::NotADocument(op.into())),
+        }
+    }
+
+    fn update_mix(&mut self, doc: &Document) {
+        let ops: Vec<&str> = doc
+            .keys()
+            .map(|k| &**k)
+            .filter(|k| UPDATE_OPERATORS.contains(k) && *k != "$do")
+            .collect();
+
+        if ops.contains(&"$dropall") {
+            for &op in ops.iter().filter(|&&op| op != "$dropall") {
+                self.at("$dropall", |v| {
+                    v.report(QueryProblemKind::MixedUpdateOperators(
+                        "$dropall".into(),
+                        op.into(),
+                    ))
+                });
+            }
+            return;
+        }
+
+        let mut updated: Vec<(&str, &str)> = Vec::new();
+        for &op in &ops {
+            let fields = match doc.get(op) {
+                Some(&Bson::Document(ref fields)) => fields,
+                _ => continue,
+            };
+            for field in fields.keys() {
+                let previous = updated
+                    .iter()
+                    .find(|&&(f, _)| f == &**field)
+                    .map(|&(_, op)| op);
+                match previous {
+                    Some(other) => self.at(op, |v| {
+                        v.at(field, |v| {
+                            v.report(QueryProblemKind::MixedUpdateOperators(
+                                op.into(),
+                                other.into(),
+                            ))
+                        })
+                    }),
+                    None => updated.push((&**field, op)),
+                }
+            }
+        }
+    }
+
+    fn actions(&mut self, value: &Bson) {
+        let doc = match *value {
+            Bson::Document(ref doc) => doc,
+            _ => return self.report(QueryProblemKind::NotADocument("$do".into())),
+        };
+        for (key, value) in doc {
+            self.at(key, |v| match *value {
+                Bson::Document(ref actions) => {
+                    for action in actions.keys() {
+                        if !ACTION_OPERATORS.contains(&&**action) {
+                            v.at(action, |v| {
+                                v.report(QueryProblemKind::UnknownOperator(action.clone()))
+                            });
+                        }
+                    }
+                }
+                _ => v.report(QueryProblemKind::NotADocument("$do".into())),
+            });
+        }
+    }
+
+    fn hints(&mut self, doc: &Document) {
+        for (key, value) in doc {
+            self.at(key, |v| match &**key {
+                "$max" | "$skip" => {
+                    let valid = match *value {
+                        Bson::I32(n) => n >= 0,
+                        Bson::I64(n) => n >= 0,
+                        _ => false,
+                    };
+                    if !valid {
+                        v.report(QueryProblemKind::InvalidLimit(key.clone()));
+                    }
+                }
+                "$orderby" => match *value {
+                    Bson::Document(ref fields) => {
+                        for (field, value) in fields {
+                            match number_value(value) {
+                                Some(n) if n == 1.0 || n == -1.0 => {}
+                                _ => v.at(field, |v| v.report(QueryProblemKind::InvalidOrderBy)),
+                            }
+                        }
+                    }
+                    _ => v.report(QueryProblemKind::NotADocument(key.clone())),
+                },
+                "$fields" => match *value {
+                    Bson::Document(ref fields) => {
+                        let included = fields.values().filter_map(number_value).map(|n| n > 0.0);
+                        if included.unique().count() > 1 {
+                            v.report(QueryProblemKind::MixedFields);
+                        }
+                    }
+                    _ => v.report(QueryProblemKind::NotADocument(key.clone())),
+                },
+                _ if HINT_OPERATORS.contains(&&**key) => {}
+                _ => v.report(QueryProblemKind::UnknownOperator(key.clone())),
+            });
+        }
+    }
+}
+
+fn is_param(value: &Bson) -> bool {
+    match *value {
+        Bson::Document(ref doc) => doc.len() == 1 && doc.contains_key(PARAM_KEY),
+        _ => false,
+    }
+}
+
+fn is_number(value: &Bson) -> bool {
+    number_value(value).is_some() || is_param(value)
+}
+
+fn number_value(value: &Bson) -> Option<f64> {
+    match *value {
+        Bson::I32(n) => Some(n as f64),
+        Bson::I64(n) => Some(n as f64),
+        Bson::FloatingPoint(n) => Some(n),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_validate_query() {
+    use super::query::Q;
+
+    assert!(Q
+        .field("name")
+        .begin("Fo")
+        .field("count")
+        .between(1, 2.5)
+        .field("tags")
+        .not()
+        .contained_in(vec!["a"])
+        .or(vec![Q.field("n").elem_match(Q.field("x").eq(1))])
+        .set("a", 1)
+        .inc("b", 2)
+        .slice("c", 3)
+        .validate()
+        .is_ok());
+    assert!(Q.field("n").eq(bson! { "a" => 1 }).validate().is_ok());
+
+    let q = Query::from(bson! {
+        "a" => { "$bt" => [1, "x"], "$foo" => 1 },
+        "b" => { "$not" => { "$in" => 1 } },
+        "$or" => [{ "c" => { "$elemMatch" => { "d" => { "$bar" => 1 } } } }, 2],
+        "$set" => { "x" => 1 },
+        "$unset" => { "x" => "" },
+        "$do" => { "y" => { "$slice" => 1, "$map" => 2 } },
+        "$baz" => 1
+    });
+    let problems: Vec<String> = match q.validate() {
+        Err(::Error::QueryValidation(e)) => e.problems.iter().map(|p| p.to_string()).collect(),
+        other => panic!("unexpected result: {:?}", other),
+    };
+    assert_eq!(
+        problems,
+        vec![
+            "a.$bt: $bt value must be an array of exactly two numbers",
+            "a.$foo: unknown operator $foo",
+            "b.$not.$in: $in value must be an array",
+            "$or.0.c.$elemMatch.d.$bar: unknown operator $bar",
+            "$or.1: $or value must be a document",
+            "$do.y.$map: unknown operator $map",
+            "$baz: unknown operator $baz",
+            "$unset.x: $unset cannot be used together with $set",
+        ]
+    );
+
+    let q = Q.field("a").eq(1).drop_all().set("b", 1);
+    assert_eq!(
+        q.validate().unwrap_err().to_string(),
+        "invalid query: $dropall: $dropall cannot be used together with $set"
+    );
+}
+
+#[test]
+fn test_validate_hints() {
+    use super::query::QH;
+
+    assert!(QH
+        .max(10)
+        .skip(2)
+        .order_by("a")
+        .asc()
+        .order_by("b")
+        .desc()
+        .field("c")
+        .include()
+        .validate()
+        .is_ok());
+
+    let qh = QueryHints::from(bson! {
+        "$max" => (-1),
+        "$orderby" => { "a" => 2 },
+        "$fields" => { "b" => 1, "c" => (-1) },
+        "$limit" => 1
+    });
+    let problems: Vec<String> = match qh.validate() {
+        Err(::Error::QueryValidation(e)) => e.problems.iter().map(|p| p.to_string()).collect(),
+        other => panic!("unexpected result: {:?}", other),
+    };
+    assert_eq!(
+        problems,
+        vec![
+            "$max: $max value must be a non-negative integer",
+            "$orderby.a: $orderby value must be 1 or -1",
+            "$fields: $fields cannot mix included and excluded fields",
+            "$limit: unknown operator $limit",
+        ]
+    );
+}
@@ -71,10 +71,49 @@
 //! If the `_id` field is not present in the BSON document, it will be generated and added
 //! automatically.
 //!
+//! `Collection::save()` replaces the stored document with the same `_id` entirely, while
+//! `Collection::save_merge()` merges the fields of the provided document into it, which is
+//! convenient for partial updates. `Collection::save_all_with_mode()` accepts a `SaveMode`
+//! which selects between these behaviors.
+//!
 //! `Collection::save_all()` method is implemented over `Collection::save()` and returns a
 //! special kind of error which contains information about errors for each save operation,
 //! if any.
 //!
+//! Documents can be removed by their ids with `Collection::remove()` and
+//! `Collection::remove_all()` methods, which also report whether the documents existed.
+//!
+//! ## Typed collections
+//!
+//! If your documents are represented by Rust structures, you can use
+//! `Database::typed_collection()` to obtain a `TypedCollection`, which converts values
+//! to BSON documents and back using serde:
+//!
+//! ```no_run
+//! #[macro_use] extern crate serde_derive;
+//! # extern crate ejdb;
+//! # use ejdb::Database;
+//! use ejdb::bson::oid::ObjectId;
+//! use ejdb::query::{Q, QH};
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Item {
+//!     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+//!     id: Option<ObjectId>,
+//!     name: String,
+//!     count: i32,
+//! }
+//!
+//! # fn main() {
+//! # let db = Database::open("/path/to/db").unwrap();
+//! let items = db.typed_collection::<Item>("items").unwrap();
+//! items.save(&Item { id: None, name: "Foo".into(), count: 10 }).unwrap();
+//! for item in items.find(Q.field("count").gt(5), QH.empty()).unwrap() {
+//!     println!("{}", item.unwrap().name);
+//! }
+//! # }
+//! ```
+//!
 //! ## Performing queries
 //!
 //! EJDB supports a pretty large subset of operations provided by MongoDB, and even has
@@ -129,6 +168,20 @@
 //! # }
 //! ```
 //!
+//! Queries and query hints can also be parsed from strings in relaxed JSON syntax, like
+//! the ones typed in the MongoDB shell, with `Query::parse()` and `QueryHints::parse()`:
+//!
+//! ```no_run
+//! # use ejdb::Database;
+//! use ejdb::query::{Query, QueryHints};
+//! # let db = Database::open("/path/to/db").unwrap();
+//! # let coll = db.collection("some_collection").unwrap();
+//!
+//! let q = Query::parse("{ name: { $begin: 'Fo' }, count: { $gt: 3 } }").unwrap();
+//! let qh = QueryHints::parse("{ $orderby: { name: 1 } }").unwrap();
+//! let items = coll.query(q, qh).find().unwrap();
+//! ```
+//!
 //! ## Transactions
 //!
 //! You can use `Collection::begin_transaction()` method which will start a transaction over
@@ -165,6 +218,86 @@
 //! }
 //! ```
 //!
+//! `Collection::transaction()` wraps this loop: it runs a closure inside a transaction,
+//! commits it if the closure returns `Ok` and aborts it otherwise. Failed commits and lock
+//! errors are retried according to a `RetryPolicy`, which can be passed explicitly
+//! to `Collection::transaction_with_retry()`.
+//!
+//! ```no_run
+//! # #[macro_use] extern crate ejdb;
+//! # use ejdb::Database;
+//! # fn main() {
+//! # let db = Database::open("/path/to/db").unwrap();
+//! # let coll = db.collection("some_collection").unwrap();
+//! let id = coll.transaction(|coll| {
+//!     let id = coll.save(bson! { "name" => "Foo" })?;
+//!     coll.save(bson! { "name" => "Bar", "foo" => (id.clone()) })?;
+//!     Ok(id)
+//! }).unwrap();
+//! # }
+//! ```
+//!
+//! ## Errors
+//!
+//! All fallible operations return `ejdb::Result`. Failures reported by EJDB itself are
+//! represented by `Error::Ejdb` variant, which contains the raw EJDB error code, its typed
+//! `ErrorKind` and a description of the failed operation. Helper methods like
+//! `Error::is_lock_error()` or `Error::is_query_error()` can be used to classify errors
+//! without matching on their messages.
+//!
+//! ## Document models
+//!
+//! Types implementing `EjdbDocument` trait declare their collection name, collection options
+//! and indices, so the collection can be set up with a single `Database::ensure()` call.
+//! With the `derive` feature enabled, this trait can be derived:
+//!
+//! ```ignore
+//! #[macro_use] extern crate ejdb;
+//! # use ejdb::Database;
+//!
+//! #[derive(EjdbDocument)]
+//! #[ejdb(collection = "users", compressed)]
+//! struct User {
+//!     #[ejdb(index = "string")]
+//!     name: String,
+//! }
+//!
+//! # fn main() {
+//! # let db = Database::open("/path/to/db").unwrap();
+//! let users = db.ensure::<User>().unwrap();
+//! # }
+//! ```
+//!
+//! ## Sharing a database between threads
+//!
+//! `Collection` objects borrow their `Database`, so they can't be stored in long-lived
+//! structures or sent to other threads. `SharedDatabase` is a clonable handle to a database
+//! protected by a lock, and its `CollectionHandle`s are owned collection handles with the same
+//! API as `Collection`. Both are `Send` and `Sync`. See `shared` module documentation for
+//! more information.
+//!
+//! ## Asynchronous access
+//!
+//! With the `async` feature enabled, `AsyncDatabase` structure is available. It moves
+//! the database to a dedicated worker thread and returns `futures` 0.3 futures and streams
+//! for all operations, so the database can be used from asynchronous code without blocking
+//! executor threads. See `async_db` module documentation for more information.
+//!
+//! ## Export and import
+//!
+//! Collections can be dumped into a directory as BSON or JSON files and loaded back with
+//! `Database::export()` and `Database::import()` methods. Both return a report with the
+//! processed collections and their record counts; see `transfer` module for more information.
+//!
+//! ```no_run
+//! # use ejdb::Database;
+//! use ejdb::transfer::{ExportFormat, ImportMode};
+//! # let db = Database::open("/path/to/db").unwrap();
+//!
+//! db.export("/path/to/backup", vec!["some_collection"], ExportFormat::Bson).unwrap();
+//! db.import("/path/to/backup", vec!["some_collection"], ImportMode::Update).unwrap();
+//! ```
+//!
 //! ## Indices
 //!
 //! It is also possible to use `Collection::index()` method to configure indices in the collection.
@@ -214,20 +347,46 @@ extern crate bitflags;
 #[macro_use]
 extern crate quick_error;
 pub extern crate bson as bson_crate;
+extern crate chrono;
 pub extern crate ejdb_sys;
+#[cfg(feature = "derive")]
+extern crate ejdb_derive;
+#[cfg(feature = "async")]
+extern crate futures;
 extern crate itertools;
 extern crate libc;
+extern crate serde;
 
 /// A reexport of `bson` crate used by this crate in public interface.
 pub use bson_crate as bson;
 
+#[cfg(feature = "derive")]
+#[doc(hidden)]
+pub use ejdb_derive::EjdbDocument;
+
+#[cfg(feature = "async")]
+pub use database::async_db::{self, AsyncDatabase};
+pub use database::bulk::{self, BulkResult, BulkWrite};
+pub use database::command;
+pub use database::compiled::CompiledQuery;
+pub use database::document::EjdbDocument;
 pub use database::indices::Index;
 pub use database::meta;
+pub use database::modify::{self, RemovedDocuments, ReturnDocument};
 pub use database::open_mode::{self, DatabaseOpenMode};
+pub use database::paginate::{self, Paginator};
+pub use database::plan::{self, QueryPlan};
 pub use database::query;
-pub use database::tx::Transaction;
-pub use database::{Collection, CollectionOptions, Database, PreparedQuery, QueryResult};
-pub use types::{Error, Result};
+pub use database::shared::{self, CollectionHandle, SharedDatabase};
+pub use database::transfer;
+pub use database::tx::{RetryPolicy, Transaction};
+pub use database::typed::{TypedCollection, TypedQueryResult};
+pub use database::{
+    Collection, CollectionIter, CollectionOptions, Database, PreparedQuery, QueryResult,
+    QueryResultIter, RawQueryResultIter, SaveMode, StoredCollectionOptions,
+};
+pub use raw::{RawBson, RawDocument};
+pub use types::{Error, ErrorKind, Result};
 
 #[macro_use]
 mod macros;
@@ -235,4 +394,5 @@ mod database;
 mod utils;
 
 pub mod ejdb_bson;
+pub mod raw;
 pub mod types;
@@ -0,0 +1,620 @@
+//! Zero-copy views over BSON documents.
+//!
+//! Converting EJDB records to `bson::Document`s requires decoding and allocating every field,
+//! even when only a few of them are needed. `RawDocument` is a borrowed view over the encoded
+//! BSON data which looks fields up lazily, directly in the underlying buffer, without
+//! allocating. Raw documents for query results are available through `QueryResult::raw_iter()`
+//! and `QueryResult::get_raw()` methods.
+//!
+//! Raw documents are validated lazily as well: malformed data is only detected when the part
+//! of the document containing it is traversed, and it is reported as an error of the lookup
+//! method.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use ejdb::Database;
+//! use ejdb::query::{Q, QH};
+//!
+//! let db = Database::open("/path/to/db").unwrap();
+//! let coll = db.collection("some_collection").unwrap();
+//! let result = coll.query(Q.field("name").exists(true), QH.empty()).find().unwrap();
+//! for doc in result.raw_iter() {
+//!     let doc = doc.unwrap();
+//!     let name: Option<&str> = doc.get_str("name").unwrap();
+//!     let city = doc.get_path("address.city").unwrap().and_then(|v| v.as_str());
+//! }
+//! ```
+
+use std::fmt;
+use std::str;
+
+use bson::{self, oid, DecoderError, Document};
+
+use {Error, Result};
+
+/// A borrowed view over an encoded BSON document.
+///
+/// See the module documentation for more information.
+#[derive(Copy, Clone, PartialEq)]
+pub struct RawDocument<'r> {
+    data: &'r [u8],
+}
+
+impl<'r> RawDocument<'r> {
+    /// Creates a raw document view over the provided buffer.
+    ///
+    /// Only the document header is checked by this method; the rest of the data is validated
+    /// when it is accessed.
+    ///
+    /// # Failures
+    ///
+    /// Returns an error if the buffer is too short or if its length does not match the
+    /// length declared in the document header.
+    pub fn new(data: &'r [u8]) -> Result<RawDocument<'r>> {
+        if data.len() < 5 {
+            return Err(malformed("document is too short"));
+        }
+        let len = read_i32(data) as usize;
+        if len != data.len() {
+            return Err(malformed("document length does not match its header"));
+        }
+        if data[len - 1] != 0 {
+            return Err(malformed("document is not terminated with a zero byte"));
+        }
+        Ok(RawDocument { data: data })
+    }
+
+    /// Returns the underlying encoded BSON data.
+    #[inline]
+    pub fn as_bytes(&self) -> &'r [u8] {
+        self.data
+    }
+
+    /// Decodes this view into an owned BSON document.
+    ///
+    /// # Failures
+    ///
+    /// Returns an error if the document is malformed.
+    pub fn to_document(&self) -> Result<Document> {
+        let mut data = self.data;
+        bson::decode_document(&mut data).map_err(|e| e.into())
+    }
+
+    /// Returns an iterator over the fields of this document.
+    ///
+    /// Each item is either a key-value pair or an error if the document is malformed; no items
+    /// are returned after an error.
+    #[inline]
+    pub fn iter(&self) -> RawDocumentIter<'r> {
+        RawDocumentIter {
+            data: &self.data[4..self.data.len() - 1],
+            failed: false,
+        }
+    }
+
+    /// Looks up a field of this document by its name.
+    ///
+    /// Returns `None` if there is no such field.
+    ///
+    /// # Failures
+    ///
+    /// Returns an error if the document is malformed.
+    pub fn get(&self, key: &str) -> Result<Option<RawBson<'r>>> {
+        for item in self.iter() {
+            let (k, v) = try!(item);
+            if k == key {
+                return Ok(Some(v));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Looks up a field by a dotted path, like `a.b.c`, descending into nested documents
+    /// and arrays.
+    ///
+    /// Array elements are addressed by their indices, like in EJDB queries: `items.0.name`.
+    /// Returns `None` if there is no such field or if some intermediate value is neither
+    /// a document nor an array.
+    ///
+    /// # Failures
+    ///
+    /// Returns an error if the document is malformed.
+    pub fn get_path(&self, path: &str) -> Result<Option<RawBson<'r>>> {
+        let mut current = *self;
+        let mut parts = path.split('.').peekable();
+        while let Some(part) = parts.next() {
+            let value = match try!(current.get(part)) {
+                Some(value) => value,
+                None => return Ok(None),
+            };
+            if parts.peek().is_none() {
+                return Ok(Some(value));
+            }
+            current = match value {
+                RawBson::Document(doc) | RawBson::Array(doc) => doc,
+                _ => return Ok(None),
+            };
+        }
+        Ok(None)
+    }
+
+    /// Looks up a string field by its name.
+    ///
+    /// # Failures
+    ///
+    /// Returns an error if the document is malformed or if the field is not a string.
+    pub fn get_str(&self, key: &str) -> Result<Option<&'r str>> {
+        self.get_typed(key, "string", RawBson::as_str)
+    }
+
+    /// Looks up a floating point field by its name.
+    ///
+    /// # Failures
+    ///
+    /// Returns an error if the document is malformed or if the field is not a double.
+    pub fn get_f64(&self, key: &str) -> Result<Option<f64>> {
+        self.get_typed(key, "double", RawBson::as_f64)
+    }
+
+    /// Looks up a 32-bit integer field by its name.
+    ///
+    /// # Failures
+    ///
+    /// Returns an error if the document is malformed or if the field is not a 32-bit integer.
+    pub fn get_i32(&self, key: &str) -> Result<Option<i32>> {
+        self.get_typed(key, "32-bit integer", RawBson::as_i32)
+    }
+
+    /// Looks up a 64-bit integer field by its name.
+    ///
+    /// # Failures
+    ///
+    /// Returns an error if the document is malformed or if the field is not a 64-bit integer.
+    pub fn get_i64(&self, key: &str) -> Result<Option<i64>> {
+        self.get_typed(key, "64-bit integer", RawBson::as_i64)
+    }
+
+    /// Looks up a boolean field by its name.
+    ///
+    /// # Failures
+    ///
+    /// Returns an error if the document is malformed or if the field is not a boolean.
+    pub fn get_bool(&self, key: &str) -> Result<Option<bool>> {
+        self.get_typed(key, "boolean", RawBson::as_bool)
+    }
+
+    /// Looks up an object id field by its name.
+    ///
+    /// # Failures
+    ///
+    /// Returns an error if the document is malformed or if the field is not an object id.
+    pub fn get_object_id(&self, key: &str) -> Result<Option<oid::ObjectId>> {
+        self.get_typed(key, "object id", RawBson::as_object_id)
+    }
+
+    /// Looks up a nested document field by its name.
+    ///
+    /// # Failures
+    ///
+    /// Returns an error if the document is malformed or if the field is not a document.
+    pub fn get_document(&self, key: &str) -> Result<Option<RawDocument<'r>>> {
+        self.get_typed(key, "document", RawBson::as_document)
+    }
+
+    /// Looks up an array field by its name.
+    ///
+    /// Arrays are represented as documents with keys equal to element indices.
+    ///
+    /// # Failures
+    ///
+    /// Returns an error if the document is malformed or if the field is not an array.
+    pub fn get_array(&self, key: &str) -> Result<Option<RawDocument<'r>>> {
+        self.get_typed(key, "array", RawBson::as_array)
+    }
+
+    fn get_typed<T, F>(&self, key: &str, expected: &str, f: F) -> Result<Option<T>>
+    where
+        F: FnOnce(&RawBson<'r>) -> Option<T>,
+    {
+        match try!(self.get(key)) {
+            Some(value) => match f(&value) {
+                Some(result) => Ok(Some(result)),
+                None => Err(format!("field {} is not a {}", key, expected).into()),
+            },
+            None => Ok(None),
+        }
+    }
+}
+
+impl<'r> fmt::Debug for RawDocument<'r> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RawDocument")
+            .field("len", &self.data.len())
+            .finish()
+    }
+}
+
+impl<'r> IntoIterator for RawDocument<'r> {
+    type Item = Result<(&'r str, RawBson<'r>)>;
+    type IntoIter = RawDocumentIter<'r>;
+
+    #[inline]
+    fn into_iter(self) -> RawDocumentIter<'r> {
+        self.iter()
+    }
+}
+
+/// An iterator over the fields of a `RawDocument`.
+///
+/// Objects of this structure are returned by `RawDocument::iter()` method.
+pub struct RawDocumentIter<'r> {
+    data: &'r [u8],
+    failed: bool,
+}
+
+impl<'r> RawDocumentIter<'r> {
+    fn next_field(&mut self) -> Result<(&'r str, RawBson<'r>)> {
+        let data = self.data;
+        let (key, rest) = try!(read_cstr(&data[1..]));
+        let element_type = data[0];
+        let (value, rest) = try!(read_value(element_type, rest));
+        self.data = rest;
+        Ok((key, value))
+    }
+}
+
+impl<'r> Iterator for RawDocumentIter<'r> {
+    type Item = Result<(&'r str, RawBson<'r>)>;
+
+    fn next(&mut self) -> Option<Result<(&'r str, RawBson<'r>)>> {
+        if self.failed || self.data.is_empty() {
+            return None;
+        }
+        let result = self.next_field();
+        self.failed = result.is_err();
+        Some(result)
+    }
+}
+
+/// A borrowed BSON value from a `RawDocument`.
+///
+/// Values which require no allocations are decoded eagerly; strings, binary data and nested
+/// documents borrow from the underlying buffer. Values of the types which are rarely used
+/// in EJDB records are represented with `RawBson::Other` variant.
+#[derive(Clone, PartialEq, Debug)]
+pub enum RawBson<'r> {
+    /// A floating point number.
+    Double(f64),
+    /// A string.
+    String(&'r str),
+    /// A nested document.
+    Document(RawDocument<'r>),
+    /// An array, represented as a document with keys equal to element indices.
+    Array(RawDocument<'r>),
+    /// Binary data with its subtype.
+    Binary(u8, &'r [u8]),
+    /// An object id.
+    ObjectId(oid::ObjectId),
+    /// A boolean.
+    Boolean(bool),
+    /// UTC datetime as a number of milliseconds since the Unix epoch.
+    UtcDatetime(i64),
+    /// The null value.
+    Null,
+    /// A regular expression with its options.
+    RegExp(&'r str, &'r str),
+    /// A 32-bit integer.
+    I32(i32),
+    /// A timestamp.
+    TimeStamp(i64),
+    /// A 64-bit integer.
+    I64(i64),
+    /// A value of some other type with its BSON element type and encoded data.
+    Other(u8, &'r [u8]),
+}
+
+impl<'r> RawBson<'r> {
+    /// Returns the value if it is a string.
+    #[inline]
+    pub fn as_str(&self) -> Option<&'r str> {
+        match *self {
+            RawBson::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Returns the value if it is a floating point number.
+    #[inline]
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            RawBson::Double(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    /// Returns the value if it is a 32-bit integer.
+    #[inline]
+    pub fn as_i32(&self) -> Option<i32> {
+        match *self {
+            RawBson::I32(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    /// Returns the value if it is a 64-bit integer.
+    #[inline]
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            RawBson::I64(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    /// Returns the value if it is a boolean.
+    #[inline]
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            RawBson::Boolean(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    /// Returns the value if it is an object id.
+    #[inline]
+    pub fn as_object_id(&self) -> Option<oid::ObjectId> {
+        match *self {
+            RawBson::ObjectId(ref id) => Some(id.clone()),
+            _ => None,
+        }
+    }
+
+    /// Returns the value if it is a nested document.
+    #[inline]
+    pub fn as_document(&self) -> Option<RawDocument<'r>> {
+        match *self {
+            RawBson::Document(doc) => Some(doc),
+            _ => None,
+        }
+    }
+
+    /// Returns the value if it is an array.
+    #[inline]
+    pub fn as_array(&self) -> Option<RawDocument<'r>> {
+        match *self {
+            RawBson::Array(doc) => Some(doc),
+            _ => None,
+        }
+    }
+
+    /// Returns the value converted to `f64` if it is a number of any type.
+    pub fn to_f64(&self) -> Option<f64> {
+        match *self {
+            RawBson::Double(n) => Some(n),
+            RawBson::I32(n) => Some(n as f64),
+            RawBson::I64(n) => Some(n as f64),
+            _ => None,
+        }
+    }
+}
+
+fn malformed(msg: &str) -> Error {
+    DecoderError::SyntaxError(format!("malformed BSON document: {}", msg)).into()
+}
+
+fn read_i32(data: &[u8]) -> i32 {
+    let mut buf = [0; 4];
+    buf.copy_from_slice(&data[..4]);
+    i32::from_le_bytes(buf)
+}
+
+fn read_i64(data: &[u8]) -> i64 {
+    let mut buf = [0; 8];
+    buf.copy_from_slice(&data[..8]);
+    i64::from_le_bytes(buf)
+}
+
+fn split(data: &[u8], n: usize) -> Result<(&[u8], &[u8])> {
+    if data.len() < n {
+        Err(malformed("unexpected end of data"))
+    } else {
+        Ok(data.split_at(n))
+    }
+}
+
+fn read_cstr(data: &[u8]) -> Result<(&str, &[u8])> {
+    match data.iter().position(|&b| b == 0) {
+        Some(n) => {
+            let s = try!(str::from_utf8(&data[..n]).map_err(|_| malformed("invalid UTF-8 string")));
+            Ok((s, &data[n + 1..]))
+        }
+        None => Err(malformed("unterminated string")),
+    }
+}
+
+fn read_len_prefixed(data: &[u8], min_len: usize) -> Result<(&[u8], &[u8])> {
+    let (len, _) = try!(split(data, 4));
+    let len = read_i32(len);
+    if len < min_len as i32 {
+        return Err(malformed("invalid length"));
+    }
+    split(data, len as usize)
+}
+
+fn read_string(data: &[u8]) -> Result<(&str, &[u8])> {
+    let (len, rest) = try!(split(data, 4));
+    let len = read_i32(len);
+    if len < 1 {
+        return Err(malformed("invalid string length"));
+    }
+    let (s, rest) = try!(split(rest, len as usize));
+    if s[s.len() - 1] != 0 {
+        return Err(malformed("string is not terminated with a zero byte"));
+    }
+    let s = try!(str::from_utf8(&s[..s.len() - 1]).map_err(|_| malformed("invalid UTF-8 string")));
+    Ok((s, rest))
+}
+
+fn read_value(element_type: u8, data: &[u8]) -> Result<(RawBson, &[u8])> {
+    Ok(match element_type {
+        0x01 => {
+            let (v, rest) = try!(split(data, 8));
+            (RawBson::Double(f64::from_bits(read_i64(v) as u64)), rest)
+        }
+        0x02 => {
+            let (s, rest) = try!(read_string(data));
+            (RawBson::String(s), rest)
+        }
+        0x03 | 0x04 => {
+            let (v, rest) = try!(read_len_prefixed(data, 5));
+            let doc = try!(RawDocument::new(v));
+            if element_type == 0x03 {
+                (RawBson::Document(doc), rest)
+            } else {
+                (RawBson::Array(doc), rest)
+            }
+        }
+        0x05 => {
+            let (len, rest) = try!(split(data, 4));
+            let len = read_i32(len);
+            if len < 0 {
+                return Err(malformed("invalid binary length"));
+            }
+            let (subtype, rest) = try!(split(rest, 1));
+            let (v, rest) = try!(split(rest, len as usize));
+            (RawBson::Binary(subtype[0], v), rest)
+        }
+        0x07 => {
+            let (v, rest) = try!(split(data, 12));
+            let mut bytes = [0; 12];
+            bytes.copy_from_slice(v);
+            (RawBson::ObjectId(oid::ObjectId::with_bytes(bytes)), rest)
+        }
+        0x08 => {
+            let (v, rest) = try!(split(data, 1));
+            (RawBson::Boolean(v[0] != 0), rest)
+        }
+        0x09 => {
+            let (v, rest) = try!(split(data, 8));
+            (RawBson::UtcDatetime(read_i64(v)), rest)
+        }
+        0x0A => (RawBson::Null, data),
+        0x0B => {
+            let (pattern, rest) = try!(read_cstr(data));
+            let (options, rest) = try!(read_cstr(rest));
+            (RawBson::RegExp(pattern, options), rest)
+        }
+        0x10 => {
+            let (v, rest) = try!(split(data, 4));
+            (RawBson::I32(read_i32(v)), rest)
+        }
+        0x11 => {
+            let (v, rest) = try!(split(data, 8));
+            (RawBson::TimeStamp(read_i64(v)), rest)
+        }
+        0x12 => {
+            let (v, rest) = try!(split(data, 8));
+            (RawBson::I64(read_i64(v)), rest)
+        }
+        // undefined, min key and max key have no data
+        0x06 | 0x7F | 0xFF => (RawBson::Other(element_type, &data[..0]), data),
+        // JavaScript code and symbol are strings
+        0x0D | 0x0E => {
+            let (_, rest) = try!(read_string(data));
+            let len = data.len() - rest.len();
+            (RawBson::Other(element_type, &data[..len]), rest)
+        }
+        // DBPointer is a string followed by an object id
+        0x0C => {
+            let (_, rest) = try!(read_string(data));
+            let (_, rest) = try!(split(rest, 12));
+            let len = data.len() - rest.len();
+            (RawBson::Other(element_type, &data[..len]), rest)
+        }
+        // JavaScript code with scope is length-prefixed
+        0x0F => {
+            let (v, rest) = try!(read_len_prefixed(data, 4));
+            (RawBson::Other(element_type, v), rest)
+        }
+        0x13 => {
+            let (v, rest) = try!(split(data, 16));
+            (RawBson::Other(element_type, v), rest)
+        }
+        _ => return Err(DecoderError::UnrecognizedElementType(element_type).into()),
+    })
+}
+
+#[test]
+fn test_raw_document() {
+    let doc = bson! {
+        "name" => "Foo",
+        "count" => 12,
+        "big" => 1234567890123i64,
+        "ratio" => 0.5,
+        "flag" => true,
+        "nothing" => (bson::Bson::Null),
+        "address" => {
+            "city" => "Bar",
+            "zip" => 12345
+        },
+        "items" => [1, "two", { "three" => 3 }]
+    };
+    let mut data = Vec::new();
+    bson::encode_document(&mut data, &doc).unwrap();
+
+    let raw = RawDocument::new(&data).unwrap();
+    assert_eq!(raw.get_str("name").unwrap(), Some("Foo"));
+    assert_eq!(raw.get_i32("count").unwrap(), Some(12));
+    assert_eq!(raw.get_i64("big").unwrap(), Some(1234567890123));
+    assert_eq!(raw.get_f64("ratio").unwrap(), Some(0.5));
+    assert_eq!(raw.get_bool("flag").unwrap(), Some(true));
+    assert_eq!(raw.get("nothing").unwrap(), Some(RawBson::Null));
+    assert_eq!(raw.get("missing").unwrap(), None);
+    assert!(raw.get_str("count").is_err());
+
+    assert_eq!(
+        raw.get_path("address.city")
+            .unwrap()
+            .and_then(|v| v.as_str()),
+        Some("Bar")
+    );
+    assert_eq!(
+        raw.get_path("items.2.three")
+            .unwrap()
+            .and_then(|v| v.as_i32()),
+        Some(3)
+    );
+    assert_eq!(raw.get_path("name.length").unwrap(), None);
+    assert_eq!(raw.get_path("address.missing").unwrap(), None);
+
+    let keys: Result<Vec<_>> = raw.iter().map(|r| r.map(|(k, _)| k)).collect();
+    assert_eq!(
+        keys.unwrap(),
+        vec!["name", "count", "big", "ratio", "flag", "nothing", "address", "items"]
+    );
+
+    assert_eq!(
+        raw.get_document("address")
+            .unwrap()
+            .unwrap()
+            .to_document()
+            .unwrap(),
+        bson! {
+            "city" => "Bar",
+            "zip" => 12345
+        }
+    );
+    assert_eq!(raw.to_document().unwrap(), doc);
+}
+
+#[test]
+fn test_raw_document_malformed() {
+    assert!(RawDocument::new(&[]).is_err());
+    assert!(RawDocument::new(&[5, 0, 0, 0, 1]).is_err());
+
+    // {"a": "b"} with a broken string length
+    let data = [14, 0, 0, 0, 2, b'a', 0, 100, 0, 0, 0, b'b', 0, 0];
+    let raw = RawDocument::new(&data).unwrap();
+    assert!(raw.get("a").is_err());
+    assert_eq!(raw.iter().count(), 1);
+}
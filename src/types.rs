@@ -2,11 +2,15 @@
 
 use std::borrow::Cow;
 use std::error;
+use std::ffi::CStr;
 use std::fmt;
 use std::io;
 use std::result;
+use std::str;
 
 use bson::{self, oid};
+use database::query::{ParseError, ValidationError};
+use ejdb_sys;
 use itertools::Itertools;
 
 /// The default result type used in this library.
@@ -66,6 +70,224 @@ impl fmt::Display for PartialSave {
     }
 }
 
+/// A partial remove error returned by `Collection::remove_all()` method.
+#[derive(Debug)]
+pub struct PartialRemove {
+    /// The actual cause of the partial remove error.
+    pub cause: Box<Error>,
+    /// A vector of object ids which have been processed successfully, each paired with
+    /// a flag which is `true` if the corresponding document existed before removal.
+    pub successful_ids: Vec<(oid::ObjectId, bool)>,
+}
+
+impl error::Error for PartialRemove {
+    fn description(&self) -> &str {
+        "remove operation completed partially"
+    }
+    fn cause(&self) -> Option<&error::Error> {
+        Some(&*self.cause)
+    }
+}
+
+impl fmt::Display for PartialRemove {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.successful_ids.is_empty() {
+            write!(f, "removed nothing due to an error: {}", self.cause)
+        } else {
+            write!(
+                f,
+                "only removed objects with ids: [{}] due to an error: {}",
+                self.successful_ids
+                    .iter()
+                    .map(|&(ref id, _)| OidHexDisplay(id.clone()))
+                    .join(", "),
+                self.cause
+            )
+        }
+    }
+}
+
+pub(crate) fn error_code_msg(code: i32) -> &'static str {
+    unsafe {
+        let msg = ejdb_sys::ejdberrmsg(code);
+        let msg_cstr = CStr::from_ptr(msg);
+        str::from_utf8_unchecked(msg_cstr.to_bytes())
+    }
+}
+
+/// A kind of an error reported by EJDB.
+///
+/// Every error code returned by `ejdbecode()` is mapped to one of these variants. Codes
+/// below 9000 come from the underlying Tokyo Cabinet storage engine, the rest are specific
+/// to EJDB. Unknown codes are mapped to `ErrorKind::Other`.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ErrorKind {
+    /// Threading error.
+    Thread,
+    /// Invalid operation.
+    InvalidOperation,
+    /// File not found.
+    FileNotFound,
+    /// No permission.
+    PermissionDenied,
+    /// Invalid meta data.
+    InvalidMetadata,
+    /// Invalid record header.
+    InvalidRecordHeader,
+    /// Open error.
+    Open,
+    /// Close error.
+    Close,
+    /// Truncate error.
+    Truncate,
+    /// Sync error.
+    Sync,
+    /// Stat error.
+    Stat,
+    /// Seek error.
+    Seek,
+    /// Read error.
+    Read,
+    /// Write error.
+    Write,
+    /// Memory map error.
+    Mmap,
+    /// Lock error, e.g. when the database or the collection is locked.
+    Lock,
+    /// Unlink error.
+    Unlink,
+    /// Rename error.
+    Rename,
+    /// Directory creation error.
+    Mkdir,
+    /// Directory removal error.
+    Rmdir,
+    /// Record already exists.
+    RecordExists,
+    /// Record not found.
+    RecordNotFound,
+    /// Transaction error, e.g. when a transaction is already active.
+    Transaction,
+    /// Invalid collection name.
+    InvalidCollectionName,
+    /// Invalid BSON object.
+    InvalidBson,
+    /// Invalid BSON object id.
+    InvalidBsonId,
+    /// Invalid query control field starting with `$`.
+    InvalidQueryControl,
+    /// `$strand`, `$stror`, `$in`, `$nin` or `$bt` key has a value which is not
+    /// a non-empty array.
+    QueryOperatorNotArray,
+    /// Inconsistent database metadata.
+    InconsistentMetadata,
+    /// Invalid field path value.
+    InvalidFieldPath,
+    /// Invalid query regexp value.
+    InvalidQueryRegex,
+    /// Result set sorting error.
+    QueryResultSorting,
+    /// Generic query error.
+    Query,
+    /// Updating failed.
+    QueryUpdateFailed,
+    /// Only one `$elemMatch` is allowed in a field path.
+    QueryOneElemMatch,
+    /// `$fields` hint cannot mix include and exclude fields.
+    QueryIncludeExclude,
+    /// Action key in `$do` block can only be one of `$join` and `$slice`.
+    QueryActionKey,
+    /// Exceeded the maximum number of collections per database.
+    MaxCollections,
+    /// Export/import error.
+    ExportImport,
+    /// JSON parsing failed.
+    JsonParse,
+    /// BSON document is too big.
+    BsonTooBig,
+    /// Invalid command.
+    InvalidCommand,
+    /// Miscellaneous or unknown error.
+    Other,
+}
+
+impl ErrorKind {
+    /// Maps an EJDB error code to the corresponding error kind.
+    pub fn from_code(code: i32) -> ErrorKind {
+        match code as u32 {
+            ejdb_sys::TCETHREAD => ErrorKind::Thread,
+            ejdb_sys::TCEINVALID => ErrorKind::InvalidOperation,
+            ejdb_sys::TCENOFILE => ErrorKind::FileNotFound,
+            ejdb_sys::TCENOPERM => ErrorKind::PermissionDenied,
+            ejdb_sys::TCEMETA => ErrorKind::InvalidMetadata,
+            ejdb_sys::TCERHEAD => ErrorKind::InvalidRecordHeader,
+            ejdb_sys::TCEOPEN => ErrorKind::Open,
+            ejdb_sys::TCECLOSE => ErrorKind::Close,
+            ejdb_sys::TCETRUNC => ErrorKind::Truncate,
+            ejdb_sys::TCESYNC => ErrorKind::Sync,
+            ejdb_sys::TCESTAT => ErrorKind::Stat,
+            ejdb_sys::TCESEEK => ErrorKind::Seek,
+            ejdb_sys::TCEREAD => ErrorKind::Read,
+            ejdb_sys::TCEWRITE => ErrorKind::Write,
+            ejdb_sys::TCEMMAP => ErrorKind::Mmap,
+            ejdb_sys::TCELOCK => ErrorKind::Lock,
+            ejdb_sys::TCEUNLINK => ErrorKind::Unlink,
+            ejdb_sys::TCERENAME => ErrorKind::Rename,
+            ejdb_sys::TCEMKDIR => ErrorKind::Mkdir,
+            ejdb_sys::TCERMDIR => ErrorKind::Rmdir,
+            ejdb_sys::TCEKEEP => ErrorKind::RecordExists,
+            ejdb_sys::TCENOREC => ErrorKind::RecordNotFound,
+            ejdb_sys::TCETR => ErrorKind::Transaction,
+            ejdb_sys::JBEINVALIDCOLNAME => ErrorKind::InvalidCollectionName,
+            ejdb_sys::JBEINVALIDBSON => ErrorKind::InvalidBson,
+            ejdb_sys::JBEINVALIDBSONPK => ErrorKind::InvalidBsonId,
+            ejdb_sys::JBEQINVALIDQCONTROL => ErrorKind::InvalidQueryControl,
+            ejdb_sys::JBEQINOPNOTARRAY => ErrorKind::QueryOperatorNotArray,
+            ejdb_sys::JBEMETANVALID => ErrorKind::InconsistentMetadata,
+            ejdb_sys::JBEFPATHINVALID => ErrorKind::InvalidFieldPath,
+            ejdb_sys::JBEQINVALIDQRX => ErrorKind::InvalidQueryRegex,
+            ejdb_sys::JBEQRSSORTING => ErrorKind::QueryResultSorting,
+            ejdb_sys::JBEQERROR => ErrorKind::Query,
+            ejdb_sys::JBEQUPDFAILED => ErrorKind::QueryUpdateFailed,
+            ejdb_sys::JBEQONEEMATCH => ErrorKind::QueryOneElemMatch,
+            ejdb_sys::JBEQINCEXCL => ErrorKind::QueryIncludeExclude,
+            ejdb_sys::JBEQACTKEY => ErrorKind::QueryActionKey,
+            ejdb_sys::JBEMAXNUMCOLS => ErrorKind::MaxCollections,
+            ejdb_sys::JBEEI => ErrorKind::ExportImport,
+            ejdb_sys::JBEEJSONPARSE => ErrorKind::JsonParse,
+            ejdb_sys::JBETOOBIGBSON => ErrorKind::BsonTooBig,
+            ejdb_sys::JBEINVALIDCMD => ErrorKind::InvalidCommand,
+            _ => ErrorKind::Other,
+        }
+    }
+
+    /// Returns `true` if this kind represents a lock contention error.
+    ///
+    /// Only `ErrorKind::Lock` is considered a lock error; `ErrorKind::Thread` is reported
+    /// when threading primitives themselves fail, and it is not a sign of contention.
+    #[inline]
+    pub fn is_lock_error(self) -> bool {
+        self == ErrorKind::Lock
+    }
+
+    /// Returns `true` if this kind represents an error in a query or query hints.
+    pub fn is_query_error(self) -> bool {
+        match self {
+            ErrorKind::InvalidQueryControl
+            | ErrorKind::QueryOperatorNotArray
+            | ErrorKind::InvalidFieldPath
+            | ErrorKind::InvalidQueryRegex
+            | ErrorKind::QueryResultSorting
+            | ErrorKind::Query
+            | ErrorKind::QueryUpdateFailed
+            | ErrorKind::QueryOneElemMatch
+            | ErrorKind::QueryIncludeExclude
+            | ErrorKind::QueryActionKey => true,
+            _ => false,
+        }
+    }
+}
+
 quick_error! {
     /// The main error type used in the library.
     #[derive(Debug)]
@@ -98,6 +320,36 @@ quick_error! {
             display("partial save: {}", err)
             cause(&*err.cause)
         }
+        /// Partial remove error returned by `Collection::remove_all()` method.
+        PartialRemove(err: PartialRemove) {
+            from()
+            description("partial remove")
+            display("partial remove: {}", err)
+            cause(&*err.cause)
+        }
+        /// Query parsing error returned by `Query::parse()` and `QueryHints::parse()`.
+        QueryParse(err: ParseError) {
+            from()
+            description("query parsing error")
+            display("query parsing error: {}", err)
+            cause(err)
+        }
+        /// Query validation error returned by `Query::validate()` and `QueryHints::validate()`.
+        QueryValidation(err: ValidationError) {
+            from()
+            description("invalid query")
+            display("invalid query: {}", err)
+            cause(err)
+        }
+        /// An error reported by EJDB itself.
+        ///
+        /// `code` is the raw EJDB error code, `kind` is its typed representation and `context`
+        /// describes the operation which has failed. The human-readable EJDB error message
+        /// is a part of this error's `Display` output.
+        Ejdb { code: i32, kind: ErrorKind, context: Cow<'static, str> } {
+            description(error_code_msg(*code))
+            display("{}: {}", context, error_code_msg(*code))
+        }
         /// Some other error.
         Other(msg: Cow<'static, str>) {
             description(&*msg)
@@ -107,3 +359,63 @@ quick_error! {
         }
     }
 }
+
+impl Error {
+    /// Creates an EJDB error out of the provided EJDB error code and context message.
+    pub fn from_code<C: Into<Cow<'static, str>>>(code: i32, context: C) -> Error {
+        Error::Ejdb {
+            code: code,
+            kind: ErrorKind::from_code(code),
+            context: context.into(),
+        }
+    }
+
+    /// Returns the kind of this error if it has been reported by EJDB.
+    ///
+    /// For partial save and remove errors the kind of their cause is returned.
+    pub fn kind(&self) -> Option<ErrorKind> {
+        match *self {
+            Error::Ejdb { kind, .. } => Some(kind),
+            Error::PartialSave(ref e) => e.cause.kind(),
+            Error::PartialRemove(ref e) => e.cause.kind(),
+            _ => None,
+        }
+    }
+
+    /// Returns the raw EJDB error code of this error if it has been reported by EJDB.
+    ///
+    /// For partial save and remove errors the code of their cause is returned.
+    pub fn code(&self) -> Option<i32> {
+        match *self {
+            Error::Ejdb { code, .. } => Some(code),
+            Error::PartialSave(ref e) => e.cause.code(),
+            Error::PartialRemove(ref e) => e.cause.code(),
+            _ => None,
+        }
+    }
+
+    /// Returns `true` if this error is caused by a locked database or collection.
+    #[inline]
+    pub fn is_lock_error(&self) -> bool {
+        self.kind().map(ErrorKind::is_lock_error).unwrap_or(false)
+    }
+
+    /// Returns `true` if this error is caused by an invalid query or query hints.
+    #[inline]
+    pub fn is_query_error(&self) -> bool {
+        self.kind().map(ErrorKind::is_query_error).unwrap_or(false)
+    }
+
+    /// Returns `true` if this error is caused by a missing record.
+    #[inline]
+    pub fn is_not_found(&self) -> bool {
+        self.kind() == Some(ErrorKind::RecordNotFound)
+    }
+
+    /// Returns `true` if this error is caused by a transaction failure, for example, when
+    /// a transaction is already active.
+    #[inline]
+    pub fn is_transaction_error(&self) -> bool {
+        self.kind() == Some(ErrorKind::Transaction)
+    }
+}
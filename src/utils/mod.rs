@@ -1,2 +1,3 @@
 pub mod bson;
+pub mod tclist;
 pub mod tcxstr;
@@ -0,0 +1,82 @@
+use std::ffi::CString;
+use std::os::raw::c_void;
+use std::slice;
+
+use ejdb_sys;
+
+pub struct TCList(*mut ejdb_sys::TCLIST);
+
+impl Drop for TCList {
+    fn drop(&mut self) {
+        unsafe {
+            ejdb_sys::tclistdel(self.0);
+        }
+    }
+}
+
+impl TCList {
+    #[inline]
+    pub fn new() -> TCList {
+        TCList(unsafe { ejdb_sys::tclistnew() })
+    }
+
+    #[inline]
+    pub unsafe fn from_ptr(ptr: *mut ejdb_sys::TCLIST) -> TCList {
+        TCList(ptr)
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        unsafe { ejdb_sys::tclistnum(self.0 as *const _) as usize }
+    }
+
+    pub fn get(&self, index: usize) -> Option<&[u8]> {
+        let mut size = 0;
+        let ptr = unsafe { ejdb_sys::tclistval(self.0 as *const _, index as i32, &mut size) };
+        if ptr.is_null() {
+            None
+        } else {
+            Some(unsafe { slice::from_raw_parts(ptr as *const u8, size as usize) })
+        }
+    }
+
+    pub fn shift(&mut self) -> Option<TCValue> {
+        let mut size = 0;
+        let ptr = unsafe { ejdb_sys::tclistshift(self.0, &mut size) };
+        if ptr.is_null() {
+            None
+        } else {
+            Some(TCValue(ptr))
+        }
+    }
+
+    #[inline]
+    pub fn push_str(&mut self, s: &CString) {
+        unsafe {
+            ejdb_sys::tclistpush2(self.0, s.as_ptr());
+        }
+    }
+
+    #[inline]
+    pub fn as_raw(&self) -> *mut ejdb_sys::TCLIST {
+        self.0
+    }
+}
+
+/// A value removed from a `TCList`; it is owned by the caller and freed on drop.
+pub struct TCValue(*mut c_void);
+
+impl Drop for TCValue {
+    fn drop(&mut self) {
+        unsafe {
+            ejdb_sys::tcfree(self.0);
+        }
+    }
+}
+
+impl TCValue {
+    #[inline]
+    pub fn as_ptr(&self) -> *mut c_void {
+        self.0
+    }
+}
@@ -1,13 +1,19 @@
 #[macro_use(bson)]
 extern crate ejdb;
 extern crate bson;
+#[cfg(feature = "async")]
+extern crate futures;
+#[macro_use]
+extern crate serde_derive;
 extern crate tempdir;
 
+use bson::oid::ObjectId;
 use tempdir::TempDir;
 
 use ejdb::meta::IndexType;
 use ejdb::query::{Q, QH};
-use ejdb::{CollectionOptions, Database};
+use ejdb::transfer::{CollectionReport, ExportFormat, ImportMode};
+use ejdb::{Collection, CollectionOptions, Database, EjdbDocument, ErrorKind, SharedDatabase};
 
 #[test]
 fn test_meta() {
@@ -91,6 +97,53 @@ fn test_meta() {
     }
 }
 
+#[test]
+fn test_collections() {
+    let (db, _dir) = make_db();
+
+    db.collection("test_1").unwrap();
+    db.collection("test_2").unwrap();
+
+    let mut names = db.collection_names().unwrap();
+    names.sort();
+    assert_eq!(names, vec!["test_1", "test_2"]);
+
+    let mut names: Vec<_> = db
+        .collections()
+        .unwrap()
+        .map(|c| c.name().to_owned())
+        .collect();
+    names.sort();
+    assert_eq!(names, vec!["test_1", "test_2"]);
+
+    // handles from the list are usable on their own
+    for coll in db.collections().unwrap() {
+        coll.save(bson!{ "name" => (coll.name()) }).unwrap();
+    }
+    let coll = db.collection("test_2").unwrap();
+    assert_eq!(coll.query(Q.field("name").eq("test_2"), QH.empty()).count().unwrap(), 1);
+}
+
+#[test]
+fn test_options() {
+    let (db, _dir) = make_db();
+
+    let coll = db.collection("test").unwrap();
+    let options = coll.options().unwrap();
+    assert_eq!(options.compressed, false);
+    assert_eq!(options.cached_records, 0);
+
+    coll.set_options(
+        options
+            .with_records(128_000)
+            .compressed(true)
+            .cached_records(512),
+    ).unwrap();
+    let options = coll.options().unwrap();
+    assert_eq!(options.compressed, true);
+    assert_eq!(options.cached_records, 512);
+}
+
 #[test]
 fn test_save_load() {
     let (db, _dir) = make_db();
@@ -138,6 +191,233 @@ fn test_save_load() {
     );
 }
 
+#[test]
+fn test_save_merge() {
+    use ejdb::SaveMode;
+
+    let (db, _dir) = make_db();
+    let coll = db.collection("test").unwrap();
+    let ids = coll
+        .save_all(vec![
+            bson! { "name" => "Foo", "count" => 1, "tags" => ["a"] },
+            bson! { "name" => "Bar", "count" => 2 },
+        ]).unwrap();
+
+    let id = coll
+        .save_merge(bson! { "_id" => (ids[0].clone()), "count" => 10 })
+        .unwrap();
+    assert_eq!(id, ids[0]);
+    assert_eq!(
+        coll.load(&ids[0]).unwrap().unwrap(),
+        bson! { "_id" => (ids[0].clone()), "name" => "Foo", "count" => 10, "tags" => ["a"] }
+    );
+
+    coll.save_all_with_mode(
+        vec![
+            bson! { "_id" => (ids[0].clone()), "name" => "Foo Foo" },
+            bson! { "_id" => (ids[1].clone()), "extra" => true },
+        ],
+        SaveMode::Merge,
+    ).unwrap();
+    assert_eq!(
+        coll.load(&ids[1]).unwrap().unwrap(),
+        bson! { "_id" => (ids[1].clone()), "name" => "Bar", "count" => 2, "extra" => true }
+    );
+    assert_eq!(
+        coll.load(&ids[0]).unwrap().unwrap().get_str("name").unwrap(),
+        "Foo Foo"
+    );
+
+    coll.save(bson! { "_id" => (ids[1].clone()), "extra" => false })
+        .unwrap();
+    assert_eq!(
+        coll.load(&ids[1]).unwrap().unwrap(),
+        bson! { "_id" => (ids[1].clone()), "extra" => false }
+    );
+}
+
+#[test]
+fn test_remove() {
+    let (db, _dir) = make_db();
+
+    let coll = db.collection("test").unwrap();
+    let ids = coll
+        .save_all(vec![
+            bson!{ "name" => "Foo", "count" => 123 },
+            bson!{ "name" => "Bar", "count" => 456 },
+        ]).unwrap();
+
+    assert_eq!(coll.remove(&ids[0]).unwrap(), true);
+    assert_eq!(coll.remove(&ids[0]).unwrap(), false);
+    assert!(coll.load(&ids[0]).unwrap().is_none());
+
+    assert_eq!(coll.remove_all(&ids).unwrap(), vec![false, true]);
+    assert!(coll.load(&ids[1]).unwrap().is_none());
+}
+
+#[test]
+fn test_sync() {
+    let (db, _dir) = make_db();
+
+    let coll_1 = db.collection("test_1").unwrap();
+    let coll_2 = db.collection("test_2").unwrap();
+    coll_1.save(bson!{ "name" => "Foo" }).unwrap();
+    coll_2.save(bson!{ "name" => "Bar" }).unwrap();
+
+    coll_1.sync().unwrap();
+    db.sync_collections(&[coll_1, coll_2]).unwrap();
+    db.sync().unwrap();
+}
+
+#[test]
+fn test_export_import() {
+    let (db, dir) = make_db();
+
+    db.collection("test_1")
+        .unwrap()
+        .save_all(vec![
+            bson!{ "name" => "Foo", "count" => 123 },
+            bson!{ "name" => "Bar", "count" => 456 },
+        ]).unwrap();
+    db.collection("test_2")
+        .unwrap()
+        .save(bson!{ "name" => "Baz" })
+        .unwrap();
+
+    let export_dir = dir.path().join("export");
+    let export_dir = export_dir.to_str().unwrap();
+    let report = db
+        .export(export_dir, vec!["test_1"], ExportFormat::Json)
+        .unwrap();
+    assert_eq!(report.path(), export_dir);
+    assert_eq!(
+        report.collections(),
+        &[CollectionReport {
+            name: "test_1".into(),
+            records: 2
+        }]
+    );
+
+    // files without export metadata are not collections
+    std::fs::write(dir.path().join("export").join("notes.json"), "{}").unwrap();
+
+    db.drop_collection("test_1", true).unwrap();
+    let report = db
+        .import(export_dir, Vec::<String>::new(), ImportMode::Replace)
+        .unwrap();
+    assert_eq!(report.collections().len(), 1);
+    assert_eq!(report.total_records(), 2);
+    let coll = db.collection("test_1").unwrap();
+    assert_eq!(coll.query(Q.empty(), QH.empty()).count().unwrap(), 2);
+
+    // the report counts imported records, not the records in the collection
+    coll.save(bson!{ "name" => "Quux" }).unwrap();
+    let report = db
+        .import(export_dir, vec!["test_1"], ImportMode::Update)
+        .unwrap();
+    assert_eq!(report.total_records(), 2);
+    assert_eq!(coll.query(Q.empty(), QH.empty()).count().unwrap(), 3);
+}
+
+#[test]
+fn test_command() {
+    use ejdb::command::{Command, CommandResponse};
+
+    let (db, dir) = make_db();
+    db.collection("test").unwrap().save(bson!{ "name" => "Foo" }).unwrap();
+
+    assert_eq!(Command::Ping.execute(&db).unwrap(), CommandResponse::Ping);
+
+    let export_dir = dir.path().join("command");
+    let response = Command::Export {
+        path: export_dir.to_str().unwrap().into(),
+        collections: vec!["test".into()],
+        format: ExportFormat::Json,
+    }.execute(&db)
+    .unwrap();
+    match response {
+        CommandResponse::Export(ref r) => assert_eq!(response.log(), Some(r.log())),
+        other => panic!("unexpected response: {:?}", other),
+    }
+
+    let response = Command::Import {
+        path: export_dir.to_str().unwrap().into(),
+        collections: vec!["test".into()],
+        mode: ImportMode::Replace,
+    }.execute(&db)
+    .unwrap();
+    match response {
+        CommandResponse::Import(_) => {}
+        other => panic!("unexpected response: {:?}", other),
+    }
+    let coll = db.collection("test").unwrap();
+    assert_eq!(coll.query(Q.empty(), QH.empty()).count().unwrap(), 1);
+}
+
+#[test]
+fn test_error_kind() {
+    let (db, _dir) = make_db();
+
+    let coll = db.collection("test").unwrap();
+    let err = coll
+        .query(Q.field("name").contained_in(Vec::<i32>::new()), QH.empty())
+        .count()
+        .unwrap_err();
+    assert!(err.is_query_error());
+    assert_eq!(err.kind(), Some(ErrorKind::QueryOperatorNotArray));
+    assert!(!err.is_lock_error());
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct Item {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    id: Option<ObjectId>,
+    name: String,
+    count: i32,
+}
+
+#[test]
+fn test_typed_collection() {
+    let (db, _dir) = make_db();
+
+    let items = db.typed_collection::<Item>("test").unwrap();
+    let id_1 = items
+        .save(&Item {
+            id: None,
+            name: "Foo".into(),
+            count: 123,
+        }).unwrap();
+    let id_2 = items
+        .save(&Item {
+            id: None,
+            name: "Bar".into(),
+            count: 456,
+        }).unwrap();
+
+    let item_1 = items.load(&id_1).unwrap().unwrap();
+    assert_eq!(
+        item_1,
+        Item {
+            id: Some(id_1.clone()),
+            name: "Foo".into(),
+            count: 123
+        }
+    );
+
+    let found: ejdb::Result<Vec<_>> = items
+        .find(Q.field("count").gt(200), QH.empty())
+        .unwrap()
+        .collect();
+    assert_eq!(
+        found.unwrap(),
+        vec![Item {
+            id: Some(id_2),
+            name: "Bar".into(),
+            count: 456
+        }]
+    );
+}
+
 #[test]
 fn test_query() {
     let (db, _dir) = make_db();
@@ -219,6 +499,852 @@ fn test_query() {
     );
 }
 
+struct Article;
+
+impl EjdbDocument for Article {
+    fn collection_name() -> &'static str {
+        "articles"
+    }
+
+    fn collection_options() -> CollectionOptions {
+        CollectionOptions::default().compressed(true)
+    }
+
+    fn ensure_indices(coll: &Collection) -> ejdb::Result<()> {
+        coll.index("title").string(true).set()?;
+        coll.index("views").number().set()?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_ensure() {
+    let (db, _dir) = make_db();
+
+    db.ensure::<Article>().unwrap();
+    // must be idempotent
+    let coll = db.ensure::<Article>().unwrap();
+    assert_eq!(coll.name(), "articles");
+    assert!(coll.options().unwrap().compressed);
+
+    let meta = db.get_metadata().unwrap();
+    let coll_meta = meta
+        .collections()
+        .find(|c| c.name() == "articles")
+        .unwrap();
+    let mut fields: Vec<_> = coll_meta
+        .indices()
+        .map(|i| (i.field().to_owned(), i.index_type()))
+        .collect();
+    fields.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(
+        fields,
+        vec![
+            ("title".to_owned(), IndexType::Lexical),
+            ("views".to_owned(), IndexType::Decimal),
+        ]
+    );
+}
+
+#[cfg(feature = "derive")]
+#[test]
+fn test_ensure_derived() {
+    #[derive(EjdbDocument)]
+    #[ejdb(large)]
+    #[allow(dead_code)]
+    struct BlogPost {
+        #[ejdb(index = "istring")]
+        title: String,
+        #[ejdb(index = "number", field = "n_views")]
+        views: i32,
+    }
+
+    assert_eq!(BlogPost::collection_name(), "blog_post");
+
+    let (db, _dir) = make_db();
+    let coll = db.ensure::<BlogPost>().unwrap();
+    assert_eq!(coll.name(), "blog_post");
+
+    let meta = db.get_metadata().unwrap();
+    let coll_meta = meta
+        .collections()
+        .find(|c| c.name() == "blog_post")
+        .unwrap();
+    let mut fields: Vec<_> = coll_meta.indices().map(|i| i.field().to_owned()).collect();
+    fields.sort();
+    assert_eq!(fields, vec!["n_views".to_owned(), "title".to_owned()]);
+}
+
+#[test]
+fn test_query_result_access() {
+    let (db, _dir) = make_db();
+    let coll = db.collection("test").unwrap();
+    coll.save_all((0..5).map(|i| bson! { "n" => i })).unwrap();
+
+    let n = |doc: ejdb::Result<bson::Document>| doc.unwrap().get_i32("n").unwrap();
+
+    let mut result = coll
+        .query(Q.empty(), QH.order_by("n").asc())
+        .find()
+        .unwrap();
+    assert_eq!(result.total(), 5);
+    assert_eq!(result.size_hint(), (5, Some(5)));
+    assert_eq!(result.get(3).map(&n), Some(3));
+    assert!(result.get(5).is_none());
+
+    let values: Vec<_> = result.iter().rev().map(&n).collect();
+    assert_eq!(values, vec![4, 3, 2, 1, 0]);
+    let values: Vec<_> = (&result).into_iter().step_by(2).map(&n).collect();
+    assert_eq!(values, vec![0, 2, 4]);
+
+    assert_eq!(result.next().map(&n), Some(0));
+    assert_eq!(result.next_back().map(&n), Some(4));
+    assert_eq!(result.len(), 3);
+    // random access and borrowing iteration ignore consumed records
+    assert_eq!(result.get(0).map(&n), Some(0));
+    assert_eq!(result.total(), 5);
+    assert_eq!(result.iter().len(), 5);
+    let values: Vec<_> = result.map(&n).collect();
+    assert_eq!(values, vec![1, 2, 3]);
+}
+
+#[test]
+fn test_raw_query_result() {
+    let (db, _dir) = make_db();
+    let coll = db.collection("test").unwrap();
+    let ids = coll
+        .save_all(vec![
+            bson! { "name" => "Foo", "address" => { "city" => "Moscow" } },
+            bson! { "name" => "Bar", "address" => { "city" => "London" } },
+        ]).unwrap();
+
+    let result = coll
+        .query(Q.empty(), QH.order_by("name").asc())
+        .find()
+        .unwrap();
+    let cities: ejdb::Result<Vec<_>> = result
+        .raw_iter()
+        .map(|doc| {
+            doc.and_then(|doc| doc.get_path("address.city"))
+                .map(|v| v.and_then(|v| v.as_str()))
+        }).collect();
+    assert_eq!(cities.unwrap(), vec![Some("London"), Some("Moscow")]);
+
+    let raw = result.get_raw(1).unwrap().unwrap();
+    assert_eq!(raw.get_object_id("_id").unwrap(), Some(ids[0].clone()));
+    assert_eq!(raw.to_document().unwrap(), result.get(1).unwrap().unwrap());
+}
+
+#[test]
+fn test_explain() {
+    let (db, _dir) = make_db();
+    let coll = db.collection("test").unwrap();
+    coll.save_all((0..10).map(|i| bson! { "name" => (format!("Foo {}", i)), "n" => i }))
+        .unwrap();
+    coll.index("name").string(true).set().unwrap();
+
+    let plan = coll
+        .query(Q.field("name").eq("Foo 3"), QH.empty())
+        .explain()
+        .unwrap();
+    let index = plan.index.unwrap();
+    assert_eq!(index.name, "sname");
+    assert_eq!(index.field, "name");
+    assert_eq!(index.index_type, IndexType::Lexical);
+    assert!(!plan.full_scan);
+    assert!(plan.count_only);
+    assert_eq!(plan.records_matched, Some(1));
+
+    let mut log = Vec::new();
+    let plan = coll
+        .query(
+            Q.field("n").gte(2),
+            QH.order_by("n").desc().skip(1).max(3),
+        ).log_output(&mut log)
+        .explain()
+        .unwrap();
+    assert!(plan.index.is_none());
+    assert!(plan.full_scan);
+    assert_eq!(plan.skip, 1);
+    assert_eq!(plan.max, Some(3));
+    assert_eq!(plan.order_fields, 1);
+    assert!(plan.count_only);
+    assert_eq!(plan.log.as_bytes(), &log[..]);
+
+    let result = coll
+        .query(Q.field("n").eq(1).set("name", "Bar"), QH.empty())
+        .explain();
+    assert!(result.is_err());
+    let n_renamed = coll
+        .query(Q.field("name").eq("Bar"), QH.empty())
+        .count()
+        .unwrap();
+    assert_eq!(n_renamed, 0);
+}
+
+#[test]
+fn test_or_query() {
+    let (db, _dir) = make_db();
+    let coll = db.collection("test").unwrap();
+    coll.save_all((0..10).map(|i| bson! { "name" => (format!("Foo {}", i)), "n" => i }))
+        .unwrap();
+    coll.index("name").string(true).set().unwrap();
+    coll.index("n").number().set().unwrap();
+
+    let result: ejdb::Result<Vec<_>> = coll
+        .query(Q.field("name").eq("Foo 1"), QH.order_by("n").asc())
+        .or_query(Q.field("n").gte(8))
+        .or_query(Q.field("name").eq("Foo 4"))
+        .find()
+        .unwrap()
+        .map(|d| d.map(|d| d.get_i32("n").unwrap()))
+        .collect();
+    assert_eq!(result.unwrap(), vec![1, 4, 8, 9]);
+
+    let n = coll
+        .query(Q.field("n").lt(2), QH.empty())
+        .or_query(Q.field("n").gt(7))
+        .count()
+        .unwrap();
+    assert_eq!(n, 4);
+}
+
+#[test]
+fn test_compiled_query() {
+    use ejdb::query::Param;
+
+    let (db, _dir) = make_db();
+    let coll = db.collection("test").unwrap();
+    coll.save_all((0..10).map(|i| bson! { "name" => (format!("Foo {}", i)), "n" => i }))
+        .unwrap();
+
+    let mut query = coll
+        .compile(
+            Q.field("n").between(Param("min"), Param("max")),
+            QH.order_by("n").desc(),
+        ).unwrap();
+    assert_eq!(query.params(), &["min".to_owned(), "max".to_owned()][..]);
+    assert!(query.bind("min", 3).count().is_err());
+
+    assert_eq!(query.bind("max", 5).count().unwrap(), 3);
+    assert_eq!(query.count().unwrap(), 3);
+    let first = query.find_one().unwrap().unwrap();
+    assert_eq!(first.get_i32("n").unwrap(), 5);
+
+    let values: ejdb::Result<Vec<_>> = query
+        .bind("min", 7)
+        .bind("max", 100)
+        .find()
+        .unwrap()
+        .map(|d| d.map(|d| d.get_i32("n").unwrap()))
+        .collect();
+    assert_eq!(values.unwrap(), vec![9, 8, 7]);
+
+    let mut all = coll.compile(Q.empty(), QH.empty()).unwrap();
+    assert!(all.params().is_empty());
+    assert_eq!(all.count().unwrap(), 10);
+    coll.save(bson! { "name" => "Bar", "n" => 10 }).unwrap();
+    assert_eq!(all.count().unwrap(), 11);
+}
+
+#[test]
+fn test_parse_query() {
+    use ejdb::query::{Query, QueryHints};
+
+    let (db, _dir) = make_db();
+    let coll = db.collection("test").unwrap();
+    coll.save_all((0..10).map(|i| bson! { "name" => (format!("Foo {}", i)), "n" => i }))
+        .unwrap();
+
+    let result: ejdb::Result<Vec<_>> = coll
+        .query(
+            Query::parse("{ name: { $begin: 'Foo' }, n: { $bt: [3, 5] } }").unwrap(),
+            QueryHints::parse("{ $orderby: { n: -1 } }").unwrap(),
+        ).find()
+        .unwrap()
+        .map(|d| d.map(|d| d.get_i32("n").unwrap()))
+        .collect();
+    assert_eq!(result.unwrap(), vec![5, 4, 3]);
+
+    match Query::parse("{\n  n: { $between: [3, 5] }\n}") {
+        Err(ejdb::Error::QueryParse(e)) => {
+            assert_eq!((e.line, e.column), (2, 8));
+            assert_eq!(e.message, "unknown field operator '$between'");
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn test_query_validation() {
+    use ejdb::query::{Query, QueryProblemKind};
+
+    let (db, _dir) = make_db();
+    let coll = db.collection("test").unwrap();
+    coll.save(bson! { "name" => "Foo", "n" => 1 }).unwrap();
+
+    let q = Query::from(bson! { "n" => { "$in" => 1 } });
+    match q.validate() {
+        Err(ejdb::Error::QueryValidation(e)) => {
+            assert_eq!(e.problems.len(), 1);
+            assert_eq!(e.problems[0].path, "n.$in");
+            assert_eq!(e.problems[0].kind, QueryProblemKind::NotAnArray("$in".into()));
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    match coll.query(&q, QH.empty()).validated().count() {
+        Err(ejdb::Error::QueryValidation(_)) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+    // queries are validated by default only in debug builds
+    match coll.query(&q, QH.empty()).count() {
+        Err(ejdb::Error::QueryValidation(_)) => assert!(cfg!(debug_assertions)),
+        Err(ref e) if e.is_query_error() => assert!(!cfg!(debug_assertions)),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert_eq!(coll.query(Q.field("n").eq(1), QH.empty()).count().unwrap(), 1);
+    assert_eq!(
+        coll.query(Q.field("n").eq(1), QH.empty())
+            .validated()
+            .count()
+            .unwrap(),
+        1
+    );
+}
+
+#[test]
+fn test_paginate() {
+    use ejdb::paginate::PageCursor;
+
+    let (db, _dir) = make_db();
+    let coll = db.collection("test").unwrap();
+    let ids = coll
+        .save_all((0..10).map(|i| bson! { "name" => (format!("Foo {}", i)), "n" => (i / 3) }))
+        .unwrap();
+
+    let mut names = Vec::new();
+    let mut token = None;
+    loop {
+        let mut pages = coll
+            .paginate(Q.field("n").gte(0), QH.order_by("n").desc(), 2)
+            .unwrap();
+        if let Some(cursor) = token.take() {
+            pages = pages.resume(cursor).unwrap();
+        }
+        let page = pages.next_page().unwrap();
+        assert!(page.documents.len() <= 2);
+        for doc in &page.documents {
+            names.push(doc.get_str("name").unwrap().to_owned());
+        }
+        match page.next {
+            Some(next) => token = Some(next.to_token().parse::<PageCursor>().unwrap()),
+            None => break,
+        }
+    }
+
+    // records are ordered by the key and then by id, both descending
+    let mut expected: Vec<_> = (0..10).map(|i| (i / 3, ids[i].bytes(), i)).collect();
+    expected.sort_by(|a, b| b.cmp(a));
+    let expected: Vec<_> = expected
+        .into_iter()
+        .map(|(_, _, i)| format!("Foo {}", i))
+        .collect();
+    assert_eq!(names, expected);
+
+    let mut pages = coll
+        .paginate(Q.field("n").lte(1), QH.order_by("name").asc(), 4)
+        .unwrap();
+    let names: Vec<_> = pages
+        .next_page()
+        .unwrap()
+        .documents
+        .iter()
+        .map(|doc| doc.get_str("name").unwrap().to_owned())
+        .collect();
+    assert_eq!(names, vec!["Foo 0", "Foo 1", "Foo 2", "Foo 3"]);
+    let page = pages.next_page().unwrap();
+    let names: Vec<_> = page
+        .documents
+        .iter()
+        .map(|doc| doc.get_str("name").unwrap().to_owned())
+        .collect();
+    assert_eq!(names, vec!["Foo 4", "Foo 5"]);
+    assert!(page.next.is_none());
+
+    assert!(coll.paginate(Q.empty(), QH.empty(), 2).is_err());
+    assert!(coll.paginate(Q.empty(), QH.order_by("n").asc().max(5), 2).is_err());
+}
+
+#[test]
+fn test_bulk_write() {
+    use ejdb::bulk::BulkOutcome;
+
+    let (db, _dir) = make_db();
+    let coll = db.collection("test").unwrap();
+    let ids = coll
+        .save_all(vec![
+            bson! { "name" => "Foo", "count" => 1 },
+            bson! { "name" => "Bar", "count" => 2 },
+        ]).unwrap();
+
+    let result = coll
+        .bulk_write()
+        .insert(bson! { "name" => "Baz", "count" => 3 })
+        .replace(bson! { "_id" => (ids[0].clone()), "name" => "Foo", "count" => 10 })
+        .update(Q.field("name").eq("Bar").inc("count", 5), QH.empty())
+        .insert(bson! { "_id" => (ids[1].clone()), "name" => "Duplicate" })
+        .remove(ids[1].clone())
+        .execute()
+        .unwrap();
+    assert!(!result.committed);
+    assert!(!result.is_success());
+    match result.outcomes[..] {
+        [BulkOutcome::Saved(_), BulkOutcome::Saved(ref id), BulkOutcome::Affected(1), BulkOutcome::Failed(ref e), BulkOutcome::Skipped] =>
+        {
+            assert_eq!(*id, ids[0]);
+            assert_eq!(e.kind(), Some(ErrorKind::RecordExists));
+        }
+        ref other => panic!("unexpected outcomes: {:?}", other),
+    }
+    assert_eq!(coll.query(Q.empty(), QH.empty()).count().unwrap(), 2);
+    assert_eq!(coll.load(&ids[0]).unwrap().unwrap().get_i32("count").unwrap(), 1);
+
+    let result = coll
+        .bulk_write()
+        .best_effort()
+        .insert(bson! { "_id" => (ids[1].clone()), "name" => "Duplicate" })
+        .replace(bson! { "name" => "No id" })
+        .update(Q.field("name").eq("Bar").inc("count", 5), QH.empty())
+        .remove(ids[0].clone())
+        .execute()
+        .unwrap();
+    assert!(result.committed);
+    assert_eq!(
+        result.errors().iter().map(|&(i, _)| i).collect::<Vec<_>>(),
+        vec![0, 1]
+    );
+    assert_eq!(coll.query(Q.empty(), QH.empty()).count().unwrap(), 1);
+    assert_eq!(coll.load(&ids[1]).unwrap().unwrap().get_i32("count").unwrap(), 7);
+}
+
+#[test]
+fn test_find_and_modify() {
+    use ejdb::ReturnDocument;
+
+    let (db, _dir) = make_db();
+    let coll = db.collection("test").unwrap();
+    coll.save_all((0..3).map(|i| bson! { "n" => i, "state" => "new" }))
+        .unwrap();
+
+    let claim = || {
+        coll.query(
+            Q.field("state").eq("new").set("state", "taken"),
+            QH.order_by("n").asc(),
+        )
+    };
+    let job = claim().find_and_modify(ReturnDocument::After, false).unwrap().unwrap();
+    assert_eq!(job.get_i32("n").unwrap(), 0);
+    assert_eq!(job.get_str("state").unwrap(), "taken");
+    let job = claim().find_and_modify(ReturnDocument::Before, false).unwrap().unwrap();
+    assert_eq!(job.get_i32("n").unwrap(), 1);
+    assert_eq!(job.get_str("state").unwrap(), "new");
+
+    let n_taken = coll
+        .query(Q.field("state").eq("taken"), QH.empty())
+        .count()
+        .unwrap();
+    assert_eq!(n_taken, 2);
+    assert!(!coll.transaction_active().unwrap());
+
+    let none = coll
+        .query(Q.field("n").eq(10).set("state", "taken"), QH.empty())
+        .find_and_modify(ReturnDocument::After, false)
+        .unwrap();
+    assert_eq!(none, None);
+
+    let created = coll
+        .query(Q.field("n").eq(10).set("n", 10).set("state", "taken"), QH.empty())
+        .find_and_modify(ReturnDocument::After, true)
+        .unwrap()
+        .unwrap();
+    assert_eq!(created.get_i32("n").unwrap(), 10);
+    assert_eq!(
+        coll.load(created.get_object_id("_id").unwrap()).unwrap(),
+        Some(created.clone())
+    );
+    assert_eq!(coll.query(Q.empty(), QH.empty()).count().unwrap(), 4);
+
+    let tx = coll.begin_transaction().unwrap();
+    assert!(claim().find_and_modify(ReturnDocument::After, false).is_err());
+    tx.abort().unwrap();
+
+    // the record is matched only by the OR query, but it is still updated
+    let job = coll
+        .query(Q.field("n").eq(100).set("state", "done"), QH.empty())
+        .or_query(Q.field("n").eq(2))
+        .find_and_modify(ReturnDocument::After, false)
+        .unwrap()
+        .unwrap();
+    assert_eq!(job.get_i32("n").unwrap(), 2);
+    assert_eq!(job.get_str("state").unwrap(), "done");
+    let stored = coll
+        .query(Q.field("n").eq(2), QH.empty())
+        .find_one()
+        .unwrap()
+        .unwrap();
+    assert_eq!(stored.get_str("state").unwrap(), "done");
+}
+
+#[test]
+fn test_find_and_remove() {
+    let (db, _dir) = make_db();
+    let coll = db.collection("test").unwrap();
+    coll.save_all((0..5).map(|i| bson! { "n" => i, "even" => (i % 2 == 0) }))
+        .unwrap();
+
+    let removed: Vec<_> = coll
+        .query(Q.field("even").eq(true), QH.order_by("n").desc().max(2))
+        .find_and_remove()
+        .unwrap()
+        .collect();
+    let ns: Vec<_> = removed.iter().map(|d| d.get_i32("n").unwrap()).collect();
+    assert_eq!(ns, vec![4, 2]);
+    for doc in &removed {
+        assert_eq!(coll.load(doc.get_object_id("_id").unwrap()).unwrap(), None);
+    }
+    assert_eq!(coll.query(Q.empty(), QH.empty()).count().unwrap(), 3);
+    assert!(!coll.transaction_active().unwrap());
+
+    let removed = coll
+        .query(Q.field("n").gt(10), QH.empty())
+        .find_and_remove()
+        .unwrap();
+    assert_eq!(removed.len(), 0);
+
+    let removed = coll
+        .query(Q.field("n").eq(0), QH.field("_id").exclude())
+        .find_and_remove();
+    assert!(removed.is_err());
+    assert_eq!(coll.query(Q.empty(), QH.empty()).count().unwrap(), 3);
+
+    let tx = coll.begin_transaction().unwrap();
+    let removed = coll.query(Q.empty(), QH.empty()).find_and_remove();
+    assert!(removed.is_err());
+    tx.abort().unwrap();
+}
+
+#[test]
+fn test_update_returning_ids() {
+    let (db, _dir) = make_db();
+    let coll = db.collection("test").unwrap();
+    let ids = coll
+        .save_all((0..4).map(|i| bson! { "n" => i, "count" => 0 }))
+        .unwrap();
+
+    let updated = coll
+        .query(
+            Q.field("n").gte(2).inc("count", 1),
+            QH.order_by("n").asc().field("n").include(),
+        )
+        .update_returning_ids()
+        .unwrap();
+    assert_eq!(updated, &ids[2..]);
+    for (i, id) in ids.iter().enumerate() {
+        let doc = coll.load(id).unwrap().unwrap();
+        assert_eq!(doc.get_i32("count").unwrap(), if i >= 2 { 1 } else { 0 });
+    }
+    assert!(!coll.transaction_active().unwrap());
+
+    let updated = coll
+        .query(Q.field("n").eq(10).upsert("n", 10), QH.empty())
+        .update_returning_ids()
+        .unwrap();
+    assert_eq!(updated.len(), 1);
+    let created = coll.load(&updated[0]).unwrap().unwrap();
+    assert_eq!(created.get_i32("n").unwrap(), 10);
+
+    let updated = coll
+        .query(Q.field("n").eq(10).upsert("n", 10), QH.empty())
+        .update_returning_ids()
+        .unwrap();
+    assert_eq!(updated, vec![created.get_object_id("_id").unwrap().clone()]);
+    assert_eq!(coll.query(Q.empty(), QH.empty()).count().unwrap(), 5);
+
+    // records matched only by the OR query are updated and returned as well
+    let updated = coll
+        .query(Q.field("n").eq(2).inc("count", 1), QH.order_by("n").asc())
+        .or_query(Q.field("n").eq(0))
+        .update_returning_ids()
+        .unwrap();
+    assert_eq!(updated, vec![ids[0].clone(), ids[2].clone()]);
+    assert_eq!(coll.load(&ids[0]).unwrap().unwrap().get_i32("count").unwrap(), 1);
+    assert_eq!(coll.load(&ids[2]).unwrap().unwrap().get_i32("count").unwrap(), 2);
+
+    // the positional update depends on the element matched by the selector
+    let items = db.collection("items").unwrap();
+    let ids = items
+        .save_all(vec![
+            bson! { "items" => [{ "sku" => "a", "qty" => 1 }, { "sku" => "b", "qty" => 1 }] },
+            bson! { "items" => [{ "sku" => "c", "qty" => 1 }] },
+        ])
+        .unwrap();
+    let updated = items
+        .query(
+            Q.field("items.sku").eq("b").set("items.$.qty", 5),
+            QH.empty(),
+        )
+        .update_returning_ids()
+        .unwrap();
+    assert_eq!(updated, vec![ids[0].clone()]);
+    let doc = items.load(&ids[0]).unwrap().unwrap();
+    let qty: Vec<_> = doc
+        .get_array("items")
+        .unwrap()
+        .iter()
+        .map(|item| item.as_document().unwrap().get_i32("qty").unwrap())
+        .collect();
+    assert_eq!(qty, vec![1, 5]);
+}
+
+#[test]
+fn test_transaction() {
+    let (db, _dir) = make_db();
+    let coll = db.collection("test").unwrap();
+
+    let id = {
+        let tx = coll.begin_transaction().unwrap();
+        assert!(coll.transaction_active().unwrap());
+        let id = coll.save(bson! { "name" => "Foo" }).unwrap();
+        tx.commit().unwrap();
+        id
+    };
+    assert!(!coll.transaction_active().unwrap());
+    assert!(coll.load(&id).unwrap().is_some());
+
+    let id = {
+        let _tx = coll.begin_transaction().unwrap();
+        coll.save(bson! { "name" => "Bar" }).unwrap()
+    };
+    assert!(coll.load(&id).unwrap().is_none());
+}
+
+#[test]
+fn test_transaction_retry_releases_lock() {
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    use ejdb::RetryPolicy;
+
+    // EJDB collections are thread-safe, but `Collection` is not `Send`
+    struct SendCollection<'c, 'db: 'c>(&'c Collection<'db>);
+    unsafe impl<'c, 'db> Send for SendCollection<'c, 'db> {}
+
+    let (db, _dir) = make_db();
+    let coll = db.collection("test").unwrap();
+
+    let (failed_tx, failed_rx) = mpsc::channel();
+    let observer = SendCollection(&coll);
+    thread::scope(|s| {
+        let active = s.spawn(move || {
+            let observer = observer;
+            failed_rx.recv().unwrap();
+            // the first attempt has failed, and the retry is delayed by the backoff
+            thread::sleep(Duration::from_millis(100));
+            observer.0.transaction_active().unwrap()
+        });
+
+        let mut attempts = 0;
+        let policy = RetryPolicy::new()
+            .attempts(2)
+            .backoff(Duration::from_millis(500));
+        coll.transaction_with_retry(policy, |coll| {
+            attempts += 1;
+            coll.save(bson! { "attempt" => attempts })?;
+            if attempts == 1 {
+                failed_tx.send(()).unwrap();
+                return Err(ejdb::Error::from_code(ejdb::ejdb_sys::TCELOCK as i32, "lock"));
+            }
+            Ok(())
+        }).unwrap();
+        assert_eq!(attempts, 2);
+        assert!(!active.join().unwrap());
+    });
+
+    let docs: Vec<_> = coll
+        .query(Q.empty(), QH.empty())
+        .find()
+        .unwrap()
+        .map(|d| d.unwrap().get_i32("attempt").unwrap())
+        .collect();
+    assert_eq!(docs, vec![2]);
+}
+
+#[test]
+fn test_transaction_closure() {
+    use ejdb::RetryPolicy;
+    use std::panic::{self, AssertUnwindSafe};
+
+    let (db, _dir) = make_db();
+    let coll = db.collection("test").unwrap();
+
+    let id = coll
+        .transaction(|coll| {
+            assert!(coll.transaction_active().unwrap());
+            coll.save(bson! { "name" => "Foo" })
+        })
+        .unwrap();
+    assert!(!coll.transaction_active().unwrap());
+    assert!(coll.load(&id).unwrap().is_some());
+
+    let mut attempts = 0;
+    let result: ejdb::Result<()> = coll.transaction(|coll| {
+        attempts += 1;
+        coll.save(bson! { "name" => "Bar" })?;
+        Err("failed".into())
+    });
+    assert!(result.is_err());
+    assert_eq!(attempts, 1);
+    assert!(!coll.transaction_active().unwrap());
+    assert_eq!(coll.query(Q.empty(), QH.empty()).count().unwrap(), 1);
+
+    let mut attempts = 0;
+    let result: ejdb::Result<()> =
+        coll.transaction_with_retry(RetryPolicy::new().attempts(3), |_| {
+            attempts += 1;
+            Err(ejdb::Error::from_code(ejdb::ejdb_sys::TCELOCK as i32, "lock"))
+        });
+    assert!(result.unwrap_err().is_lock_error());
+    assert_eq!(attempts, 3);
+
+    // threading errors are not lock contention and are not retried
+    let mut attempts = 0;
+    let result: ejdb::Result<()> =
+        coll.transaction_with_retry(RetryPolicy::new().attempts(3), |_| {
+            attempts += 1;
+            Err(ejdb::Error::from_code(ejdb::ejdb_sys::TCETHREAD as i32, "thread"))
+        });
+    assert!(!result.unwrap_err().is_lock_error());
+    assert_eq!(attempts, 1);
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let _: ejdb::Result<()> = coll.transaction(|coll| {
+            coll.save(bson! { "name" => "Baz" }).unwrap();
+            panic!("aborted");
+        });
+    }));
+    assert!(result.is_err());
+    assert!(!coll.transaction_active().unwrap());
+    assert_eq!(coll.query(Q.empty(), QH.empty()).count().unwrap(), 1);
+}
+
+#[test]
+fn test_shared_database() {
+    use std::thread;
+
+    let (db, _dir) = make_db();
+    let db = SharedDatabase::new(db);
+    let coll = db.collection("test").unwrap();
+    coll.index("n", |index| index.number().set()).unwrap();
+
+    let workers: Vec<_> = (0..4)
+        .map(|i| {
+            let coll = coll.clone();
+            thread::spawn(move || {
+                coll.transaction(|coll| {
+                    for j in 0..10 {
+                        coll.save(bson! { "n" => (i * 10 + j) })?;
+                    }
+                    Ok(())
+                })
+            })
+        }).collect();
+    for worker in workers {
+        worker.join().unwrap().unwrap();
+    }
+
+    assert_eq!(coll.query(Q.empty(), QH.empty()).count().unwrap(), 40);
+    let doc = coll
+        .query(Q.field("n").eq(25), QH.empty())
+        .find_one()
+        .unwrap()
+        .unwrap();
+    let id = doc.get_object_id("_id").unwrap().clone();
+    assert!(coll.load(&id).unwrap().is_some());
+    assert!(coll.remove(&id).unwrap());
+    assert_eq!(coll.query(Q.empty(), QH.empty()).find().unwrap().count(), 39);
+
+    assert!(db.get_collection("missing").unwrap().is_none());
+    db.with(|db| db.drop_collection("test", true)).unwrap();
+    assert!(coll.load(&id).is_err());
+
+    // the handle finds the collection again once it is recreated
+    db.with(|db| db.collection("test").map(|_| ())).unwrap();
+    assert!(coll.save(bson! { "n" => 0 }).is_ok());
+
+    let panicking = db.clone();
+    assert!(
+        thread::spawn(move || panicking.with(|_| -> ejdb::Result<()> { panic!("failure") }))
+            .join()
+            .is_err()
+    );
+    assert!(db.lock().is_err());
+    assert!(coll.save(bson! { "n" => 0 }).is_err());
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn test_async_database() {
+    use futures::executor::block_on;
+    use futures::StreamExt;
+
+    let dir = TempDir::new("ejdb").expect("cannot create temporary directory");
+    let db = ejdb::AsyncDatabase::open(dir.path().join("db").to_str().unwrap()).unwrap();
+
+    let id = block_on(db.save("test", bson! { "name" => "Foo", "count" => 1 })).unwrap();
+    let doc = block_on(db.load("test", id.clone())).unwrap().unwrap();
+    assert_eq!(doc.get_str("name").unwrap(), "Foo");
+
+    let ids = block_on(db.transaction("test", |coll| {
+        let a = coll.save(bson! { "name" => "Bar", "count" => 2 })?;
+        let b = coll.save(bson! { "name" => "Baz", "count" => 3 })?;
+        Ok(vec![a, b])
+    })).unwrap();
+    assert_eq!(ids.len(), 2);
+
+    let failed = block_on(db.transaction("test", |coll| -> ejdb::Result<()> {
+        coll.save(bson! { "name" => "Quux", "count" => 4 })?;
+        Err("rollback".into())
+    }));
+    assert!(failed.is_err());
+
+    let count = block_on(db.query("test", Q.empty(), QH.empty()).count()).unwrap();
+    assert_eq!(count, 3);
+
+    let updated = block_on(
+        db.query("test", Q.field("count").gt(1).inc("count", 10), QH.empty())
+            .update(),
+    ).unwrap();
+    assert_eq!(updated, 2);
+
+    let docs: Vec<_> = block_on(
+        db.query("test", Q.field("count").gt(10), QH.order_by("count").asc())
+            .find()
+            .collect(),
+    );
+    let names: Vec<_> = docs
+        .into_iter()
+        .map(|d| d.unwrap().get_str("name").unwrap().to_owned())
+        .collect();
+    assert_eq!(names, vec!["Bar".to_owned(), "Baz".to_owned()]);
+
+    let panicked = block_on(db.execute(|_| -> ejdb::Result<()> { panic!("oops") }));
+    assert!(panicked.is_err());
+    // the worker thread survives panics
+    assert!(block_on(db.load("test", id)).unwrap().is_some());
+}
+
 fn make_db() -> (Database, TempDir) {
     let dir = TempDir::new("ejdb").expect("cannot create temporary directory");
     let db =